@@ -1,14 +1,22 @@
+// PyO3 0.22 expands #[pymethods] into `PyErr::from(PyErr)` conversions
+#![allow(clippy::useless_conversion)]
+
+use std::collections::HashMap;
+
 pub mod tokenizer;
-use tokenizer::{Tokenizer, TokenConfig};
+use tokenizer::{Tokenizer, TokenConfig, Encoding, BatchEncoding, BatchOptions, Truncation, AddedToken, DecodeStream};
+use tokenizer::{NormalizerPipeline, PreTokenizerPipeline, PostProcessor, DecoderPipeline};
+use tokenizer::{Model, Unigram, WordPiece};
 
-use std::collections::{HashMap, HashSet};
 use wasm_bindgen::prelude::*;
 use serde_wasm_bindgen::{to_value, from_value};
 
 #[cfg(not(target_arch = "wasm32"))]
 use pyo3::prelude::*;
 #[cfg(not(target_arch = "wasm32"))]
-use pyo3::exceptions::{PyValueError, PyIOError};
+use pyo3::exceptions::{PyIOError, PyIndexError, PyKeyError, PyValueError};
+#[cfg(not(target_arch = "wasm32"))]
+use tokenizer::{Padding, PaddingSide, TruncationStrategy, TokenizerError, TrainOptions};
 
 
 
@@ -21,83 +29,446 @@ pub struct TokenizerJs {
 #[wasm_bindgen]
 impl TokenizerJs {
     #[wasm_bindgen(constructor)]
-    pub fn new(vocabulary: JsValue, merge_rules: JsValue, config: JsValue) -> TokenizerJs {
-        let vocabulary: HashSet<String> = from_value(vocabulary).unwrap();
-        let merge_rules: Vec<(String, String)> = from_value(merge_rules).unwrap();
-        let config = from_value(config).unwrap_or(TokenConfig::new());
-        TokenizerJs {
-            tokenizer: Tokenizer::new(vocabulary, merge_rules, config)
-        }
+    pub fn new(vocabulary: JsValue, merge_rules: JsValue, config: JsValue) -> Result<TokenizerJs, JsError> {
+        let vocabulary: Vec<String> = from_value(vocabulary)?;
+        let merge_rules: Vec<(String, String)> = from_value(merge_rules)?;
+        let config = if config.is_undefined() { TokenConfig::new() } else { from_value(config)? };
+        Ok(TokenizerJs {
+            tokenizer: Tokenizer::new(vocabulary, merge_rules, config)?
+        })
+    }
+
+    #[wasm_bindgen(js_name = fromJson)]
+    pub fn from_json(json: &str) -> Result<TokenizerJs, JsError> {
+        let tokenizer = Tokenizer::from_json(json)?;
+        Ok(TokenizerJs { tokenizer })
+    }
+
+    // Import a HuggingFace `tokenizer.json` with a BPE model
+    #[wasm_bindgen(js_name = fromHuggingface)]
+    pub fn from_huggingface(json: &str) -> Result<TokenizerJs, JsError> {
+        Ok(TokenizerJs { tokenizer: Tokenizer::from_huggingface_json(json)? })
+    }
+
+    // Build a byte-level tokenizer from the contents of a `.tiktoken` file, with ids equal to ranks.
+    // `specialTokens` maps each special token to its id
+    #[wasm_bindgen(js_name = fromTiktoken)]
+    pub fn from_tiktoken(data: &str, pattern: &str, special_tokens: JsValue) -> Result<TokenizerJs, JsError> {
+        let special_tokens: HashMap<String, usize> = if special_tokens.is_undefined() { HashMap::new() } else { from_value(special_tokens)? };
+        Ok(TokenizerJs { tokenizer: Tokenizer::from_tiktoken(data, pattern, &special_tokens)? })
     }
 
     #[wasm_bindgen(getter, js_name = getVocabulary)]
-    pub fn get_vocabulary(&self) -> JsValue {
-        to_value(&self.tokenizer.get_vocabulary()).unwrap()
+    pub fn get_vocabulary(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.get_vocabulary())?)
     }
 
     #[wasm_bindgen(getter, js_name = getMergeRules)]
-    pub fn get_merge_rules(&self) -> JsValue {
-        to_value(&self.tokenizer.get_merge_rules()).unwrap()
+    pub fn get_merge_rules(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.get_merge_rules())?)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn mode(&self) -> String {
+        self.tokenizer.mode.to_string()
+    }
+
+    #[wasm_bindgen(js_name = setMode)]
+    pub fn set_mode(&mut self, mode: &str) -> Result<(), JsError> {
+        self.tokenizer.set_mode(mode.parse()?);
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter, js_name = specialTokenMode)]
+    pub fn special_token_mode(&self) -> String {
+        self.tokenizer.special_token_mode.to_string()
+    }
+
+    #[wasm_bindgen(js_name = setSpecialTokenMode)]
+    pub fn set_special_token_mode(&mut self, mode: &str) -> Result<(), JsError> {
+        self.tokenizer.set_special_token_mode(mode.parse()?);
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter, js_name = byteFallback)]
+    pub fn byte_fallback(&self) -> bool {
+        self.tokenizer.byte_fallback
+    }
+
+    #[wasm_bindgen(js_name = enableByteFallback)]
+    pub fn enable_byte_fallback(&mut self) -> Result<(), JsError> {
+        Ok(self.tokenizer.enable_byte_fallback()?)
+    }
+
+    #[wasm_bindgen(getter, js_name = addedTokens)]
+    pub fn added_tokens(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.config.added_tokens)?)
+    }
+
+    // Takes `{value, special, single_word, lstrip, rstrip, normalized}` objects and returns their ids
+    #[wasm_bindgen(js_name = addTokens)]
+    pub fn add_tokens(&mut self, tokens: JsValue) -> Result<JsValue, JsError> {
+        let tokens: Vec<AddedToken> = from_value(tokens)?;
+        Ok(to_value(&self.tokenizer.add_tokens(tokens)?)?)
     }
 
     #[wasm_bindgen(js_name = getToken)]
-    pub fn get_token(&self, index: usize) -> JsValue {
-        to_value(&self.tokenizer.get_token(index)).unwrap()
+    pub fn get_token(&self, index: usize) -> Option<String> {
+        self.tokenizer.get_token(index)
     }
 
     #[wasm_bindgen(js_name = getIndex)]
-    pub fn get_index(&self, token: &str) -> JsValue {
-        to_value(&self.tokenizer.get_index(token)).unwrap()
+    pub fn get_index(&self, token: &str) -> Option<usize> {
+        self.tokenizer.get_index(token)
     }
 
     #[wasm_bindgen(js_name = getTokens)]
-    pub fn get_tokens(&self, indices: JsValue) -> JsValue {
-        let indices: Result<Vec<usize>, _> = from_value(indices);
-        match indices {
-            Ok(indices) => {
-                let tokens = self.tokenizer.get_tokens(&indices);
-                to_value(&tokens).unwrap_or_else(|e| to_value(&format!("Failed to serialize tokens: {}", e)).unwrap())
-            },
-            Err(e) => to_value(&format!("Failed to deserialize indices: {}", e)).unwrap()
-        }
+    pub fn get_tokens(&self, indices: JsValue) -> Result<JsValue, JsError> {
+        let indices: Vec<usize> = from_value(indices)?;
+        Ok(to_value(&self.tokenizer.get_tokens(&indices)?)?)
     }
 
     #[wasm_bindgen(js_name = getIndices)]
-    pub fn get_indices(&self, tokens: JsValue) -> JsValue {
-        let tokens: Vec<String> = from_value(tokens).unwrap();
-        to_value(&self.tokenizer.get_indices(&tokens)).unwrap()
+    pub fn get_indices(&self, tokens: JsValue) -> Result<JsValue, JsError> {
+        let tokens: Vec<String> = from_value(tokens)?;
+        Ok(to_value(&self.tokenizer.get_indices(&tokens)?)?)
     }
 
     #[wasm_bindgen(js_name = tokenize)]
-    pub fn tokenize(&self, text: &str) -> JsValue {
-        to_value(&self.tokenizer.tokenize(text)).unwrap()
+    pub fn tokenize(&self, text: &str) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.tokenize(text))?)
+    }
+
+    #[wasm_bindgen(js_name = encode)]
+    pub fn encode(&self, text: &str) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.encode(text))?)
+    }
+
+    #[wasm_bindgen(js_name = encodePair)]
+    pub fn encode_pair(&self, first: &str, second: &str) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.encode_pair(first, second))?)
+    }
+
+    #[wasm_bindgen(js_name = encodeTruncated)]
+    pub fn encode_truncated(&self, text: &str, pair: Option<String>, truncation: JsValue) -> Result<JsValue, JsError> {
+        let truncation: Truncation = from_value(truncation)?;
+        Ok(to_value(&self.tokenizer.encode_truncated(text, pair.as_deref(), &truncation)?)?)
+    }
+
+    // Seeds are u32 so they stay plain numbers in JS
+    #[wasm_bindgen(js_name = encodeWithDropout)]
+    pub fn encode_with_dropout(&self, text: &str, p: f64, seed: u32) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.encode_with_dropout(text, p, seed as u64)?)?)
+    }
+
+    #[wasm_bindgen(js_name = sampleEncodings)]
+    pub fn sample_encodings(&self, text: &str, n: usize, p: f64, seed: u32) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.sample_encodings(text, n, p, seed as u64)?)?)
+    }
+
+    #[wasm_bindgen(js_name = encodeNbest)]
+    pub fn encode_nbest(&self, text: &str, n: usize, p: f64, seed: u32) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.encode_nbest(text, n, p, seed as u64)?)?)
+    }
+
+    #[wasm_bindgen(js_name = encodeBatch)]
+    pub fn encode_batch(&self, texts: JsValue, options: JsValue) -> Result<JsValue, JsError> {
+        let texts: Vec<String> = from_value(texts)?;
+        let options: BatchOptions = if options.is_undefined() { BatchOptions::default() } else { from_value(options)? };
+        Ok(to_value(&self.tokenizer.encode_batch(&texts, &options)?)?)
+    }
+
+    #[wasm_bindgen(js_name = countTokens)]
+    pub fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer.count_tokens(text)
+    }
+
+    #[wasm_bindgen(js_name = truncateToTokens)]
+    pub fn truncate_to_tokens(&self, text: &str, n: usize) -> String {
+        self.tokenizer.truncate_to_tokens(text, n).to_string()
+    }
+
+    #[wasm_bindgen(js_name = countTokensBatch)]
+    pub fn count_tokens_batch(&self, texts: JsValue) -> Result<JsValue, JsError> {
+        let texts: Vec<String> = from_value(texts)?;
+        Ok(to_value(&self.tokenizer.count_tokens_batch(&texts))?)
+    }
+
+    #[wasm_bindgen(js_name = truncateToTokensBatch)]
+    pub fn truncate_to_tokens_batch(&self, texts: JsValue, n: usize) -> Result<JsValue, JsError> {
+        let texts: Vec<String> = from_value(texts)?;
+        Ok(to_value(&self.tokenizer.truncate_to_tokens_batch(&texts, n))?)
     }
 
     #[wasm_bindgen(js_name = detokenize)]
-    pub fn detokenize(&self, indices: JsValue) -> String {
-        let indices: Vec<usize> = from_value(indices).unwrap();
-        self.tokenizer.detokenize(&indices)
+    pub fn detokenize(&self, indices: JsValue) -> Result<String, JsError> {
+        let indices: Vec<usize> = from_value(indices)?;
+        Ok(self.tokenizer.detokenize(&indices))
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn cased(&self) -> bool {
+        self.tokenizer.is_cased()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn normalizer(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.normalizer)?)
+    }
+
+    #[wasm_bindgen(js_name = setNormalizer)]
+    pub fn set_normalizer(&mut self, normalizer: JsValue) -> Result<(), JsError> {
+        let normalizer: NormalizerPipeline = from_value(normalizer)?;
+        self.tokenizer.set_normalizer(normalizer);
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter, js_name = preTokenizer)]
+    pub fn pre_tokenizer(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.pre_tokenizer)?)
+    }
+
+    #[wasm_bindgen(js_name = setPreTokenizer)]
+    pub fn set_pre_tokenizer(&mut self, pre_tokenizer: JsValue) -> Result<(), JsError> {
+        let pre_tokenizer: PreTokenizerPipeline = from_value(pre_tokenizer)?;
+        self.tokenizer.set_pre_tokenizer(pre_tokenizer);
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter, js_name = postProcessor)]
+    pub fn post_processor(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.post_processor)?)
     }
 
-    #[wasm_bindgen(js_name = cleanText)]
-    pub fn clean_text(text: &str) -> String {
-        Tokenizer::clean_text(text)
+    #[wasm_bindgen(js_name = setPostProcessor)]
+    pub fn set_post_processor(&mut self, post_processor: JsValue) -> Result<(), JsError> {
+        let post_processor: Option<PostProcessor> = from_value(post_processor)?;
+        Ok(self.tokenizer.set_post_processor(post_processor)?)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn decoder(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.tokenizer.decoder)?)
+    }
+
+    #[wasm_bindgen(js_name = setDecoder)]
+    pub fn set_decoder(&mut self, decoder: JsValue) -> Result<(), JsError> {
+        let decoder: DecoderPipeline = from_value(decoder)?;
+        self.tokenizer.set_decoder(decoder);
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = preTokenize)]
+    pub fn pre_tokenize(&self, text: &str) -> Result<JsValue, JsError> {
+        let pieces: Vec<&str> = self.tokenizer.pre_tokenize(text).into_iter().map(|(_, piece)| piece).collect();
+        Ok(to_value(&pieces)?)
+    }
+
+    #[wasm_bindgen(js_name = normalize)]
+    pub fn normalize(&self, text: &str) -> String {
+        self.tokenizer.normalize(text)
     }
 
     #[wasm_bindgen(js_name = save)]
-    pub fn save(&self, path: &str) -> JsValue {
-        match self.tokenizer.save(path) {
-            Ok(result) => to_value(&result).unwrap(),
-            Err(e) => to_value(&format!("Error saving tokenizer: {}", e)).unwrap(),
-        }
+    pub fn save(&self, path: &str) -> Result<(), JsError> {
+        Ok(self.tokenizer.save(path)?)
+    }
+
+    // Export as a HuggingFace `tokenizer.json` string
+    #[wasm_bindgen(js_name = toHuggingface)]
+    pub fn to_huggingface(&self) -> Result<String, JsError> {
+        Ok(self.tokenizer.to_huggingface_json()?)
     }
 
     #[wasm_bindgen(js_name = load)]
-    pub fn load(path: &str) -> JsValue {
-        match Tokenizer::load(path) {
-            Ok(tokenizer) => to_value(&tokenizer).unwrap(),
-            Err(e) => to_value(&format!("Error loading tokenizer: {}", e)).unwrap(),
-        }
+    pub fn load(path: &str) -> Result<TokenizerJs, JsError> {
+        Ok(TokenizerJs { tokenizer: Tokenizer::load(path)? })
+    }
+}
+
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct DecodeStreamJs {
+    stream: DecodeStream,
+}
+
+#[wasm_bindgen]
+impl DecodeStreamJs {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> DecodeStreamJs {
+        DecodeStreamJs::default()
+    }
+
+    // Newly completed text, or undefined while a character is incomplete
+    #[wasm_bindgen(js_name = step)]
+    pub fn step(&mut self, tokenizer: &TokenizerJs, index: usize) -> Option<String> {
+        self.stream.step(&tokenizer.tokenizer, index)
+    }
+
+    #[wasm_bindgen(js_name = finish)]
+    pub fn finish(&mut self, tokenizer: &TokenizerJs) -> String {
+        self.stream.finish(&tokenizer.tokenizer)
+    }
+}
+
+#[wasm_bindgen]
+pub struct UnigramJs {
+    unigram: Unigram,
+}
+
+#[wasm_bindgen]
+impl UnigramJs {
+    #[wasm_bindgen(constructor)]
+    pub fn new(vocabulary: JsValue, scores: JsValue, config: JsValue) -> Result<UnigramJs, JsError> {
+        let vocabulary: Vec<String> = from_value(vocabulary)?;
+        let scores: Vec<f64> = from_value(scores)?;
+        let config = if config.is_undefined() { TokenConfig::new() } else { from_value(config)? };
+        Ok(UnigramJs {
+            unigram: Unigram::new(vocabulary, scores, config)?
+        })
+    }
+
+    #[wasm_bindgen(js_name = fromJson)]
+    pub fn from_json(json: &str) -> Result<UnigramJs, JsError> {
+        Ok(UnigramJs { unigram: Unigram::from_json(json)? })
+    }
+
+    #[wasm_bindgen(getter, js_name = getVocabulary)]
+    pub fn get_vocabulary(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.unigram.get_vocabulary())?)
+    }
+
+    #[wasm_bindgen(getter, js_name = getScores)]
+    pub fn get_scores(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.unigram.scores)?)
+    }
+
+    #[wasm_bindgen(js_name = getToken)]
+    pub fn get_token(&self, index: usize) -> Option<String> {
+        self.unigram.get_token(index)
+    }
+
+    #[wasm_bindgen(js_name = getIndex)]
+    pub fn get_index(&self, token: &str) -> Option<usize> {
+        self.unigram.get_index(token)
+    }
+
+    #[wasm_bindgen(js_name = getTokens)]
+    pub fn get_tokens(&self, indices: JsValue) -> Result<JsValue, JsError> {
+        let indices: Vec<usize> = from_value(indices)?;
+        Ok(to_value(&self.unigram.get_tokens(&indices)?)?)
+    }
+
+    #[wasm_bindgen(js_name = getIndices)]
+    pub fn get_indices(&self, tokens: JsValue) -> Result<JsValue, JsError> {
+        let tokens: Vec<String> = from_value(tokens)?;
+        Ok(to_value(&self.unigram.get_indices(&tokens)?)?)
+    }
+
+    #[wasm_bindgen(js_name = tokenize)]
+    pub fn tokenize(&self, text: &str) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.unigram.tokenize(text))?)
+    }
+
+    #[wasm_bindgen(js_name = detokenize)]
+    pub fn detokenize(&self, indices: JsValue) -> Result<String, JsError> {
+        let indices: Vec<usize> = from_value(indices)?;
+        Ok(self.unigram.detokenize(&indices))
+    }
+
+    #[wasm_bindgen(js_name = normalize)]
+    pub fn normalize(&self, text: &str) -> String {
+        self.unigram.normalize(text)
+    }
+
+    #[wasm_bindgen(js_name = save)]
+    pub fn save(&self, path: &str) -> Result<(), JsError> {
+        Ok(self.unigram.save(path)?)
+    }
+
+    #[wasm_bindgen(js_name = load)]
+    pub fn load(path: &str) -> Result<UnigramJs, JsError> {
+        Ok(UnigramJs { unigram: Unigram::load(path)? })
+    }
+}
+
+#[wasm_bindgen]
+pub struct WordPieceJs {
+    wordpiece: WordPiece,
+}
+
+#[wasm_bindgen]
+impl WordPieceJs {
+    #[wasm_bindgen(constructor)]
+    pub fn new(vocabulary: JsValue, config: JsValue) -> Result<WordPieceJs, JsError> {
+        let vocabulary: Vec<String> = from_value(vocabulary)?;
+        let config = if config.is_undefined() { TokenConfig::new() } else { from_value(config)? };
+        Ok(WordPieceJs {
+            wordpiece: WordPiece::new(vocabulary, config)?
+        })
+    }
+
+    #[wasm_bindgen(js_name = fromJson)]
+    pub fn from_json(json: &str) -> Result<WordPieceJs, JsError> {
+        Ok(WordPieceJs { wordpiece: WordPiece::from_json(json)? })
+    }
+
+    #[wasm_bindgen(getter, js_name = getVocabulary)]
+    pub fn get_vocabulary(&self) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.wordpiece.get_vocabulary())?)
+    }
+
+    #[wasm_bindgen(getter, js_name = continuingSubwordPrefix)]
+    pub fn continuing_subword_prefix(&self) -> String {
+        self.wordpiece.continuing_subword_prefix.clone()
+    }
+
+    #[wasm_bindgen(js_name = getToken)]
+    pub fn get_token(&self, index: usize) -> Option<String> {
+        self.wordpiece.get_token(index)
+    }
+
+    #[wasm_bindgen(js_name = getIndex)]
+    pub fn get_index(&self, token: &str) -> Option<usize> {
+        self.wordpiece.get_index(token)
+    }
+
+    #[wasm_bindgen(js_name = getTokens)]
+    pub fn get_tokens(&self, indices: JsValue) -> Result<JsValue, JsError> {
+        let indices: Vec<usize> = from_value(indices)?;
+        Ok(to_value(&self.wordpiece.get_tokens(&indices)?)?)
+    }
+
+    #[wasm_bindgen(js_name = getIndices)]
+    pub fn get_indices(&self, tokens: JsValue) -> Result<JsValue, JsError> {
+        let tokens: Vec<String> = from_value(tokens)?;
+        Ok(to_value(&self.wordpiece.get_indices(&tokens)?)?)
+    }
+
+    #[wasm_bindgen(js_name = tokenize)]
+    pub fn tokenize(&self, text: &str) -> Result<JsValue, JsError> {
+        Ok(to_value(&self.wordpiece.tokenize(text))?)
+    }
+
+    #[wasm_bindgen(js_name = detokenize)]
+    pub fn detokenize(&self, indices: JsValue) -> Result<String, JsError> {
+        let indices: Vec<usize> = from_value(indices)?;
+        Ok(self.wordpiece.detokenize(&indices))
+    }
+
+    #[wasm_bindgen(js_name = normalize)]
+    pub fn normalize(&self, text: &str) -> String {
+        self.wordpiece.normalize(text)
+    }
+
+    #[wasm_bindgen(js_name = save)]
+    pub fn save(&self, path: &str) -> Result<(), JsError> {
+        Ok(self.wordpiece.save(path)?)
+    }
+
+    #[wasm_bindgen(js_name = load)]
+    pub fn load(path: &str) -> Result<WordPieceJs, JsError> {
+        Ok(WordPieceJs { wordpiece: WordPiece::load(path)? })
     }
 }
 
@@ -107,33 +478,43 @@ struct TokenizerPy {
     tokenizer: Tokenizer,
 }
 
+// I/O failures raise OSError, unknown ids IndexError, unknown tokens KeyError and the rest ValueError
+#[cfg(not(target_arch = "wasm32"))]
+impl From<TokenizerError> for PyErr {
+    fn from(error: TokenizerError) -> Self {
+        let message = error.to_string();
+        match error {
+            TokenizerError::Io(_) => PyIOError::new_err(message),
+            TokenizerError::UnknownIndex(_) => PyIndexError::new_err(message),
+            TokenizerError::UnknownToken(_) => PyKeyError::new_err(message),
+            TokenizerError::Json(_)
+            | TokenizerError::Regex(_)
+            | TokenizerError::MissingSpecialToken(_)
+            | TokenizerError::InvalidConfig(_) => PyValueError::new_err(message),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn to_json<T: serde::Serialize>(value: &T) -> PyResult<String> {
+    Ok(serde_json::to_string(value).map_err(TokenizerError::from)?)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn from_json<T: serde::de::DeserializeOwned>(json: &str) -> PyResult<T> {
+    Ok(serde_json::from_str(json).map_err(TokenizerError::from)?)
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[pymethods]
 impl TokenizerPy {
     #[new]
     fn new(config_path: &str) -> PyResult<Self> {
-        // Read and parse the JSON configuration file
-        let data = std::fs::read_to_string(config_path)
-            .map_err(|e| PyErr::new::<PyIOError, _>(format!("Error reading config file: {}", e)))?;
-        
-        let config: HashMap<String, serde_json::Value> = serde_json::from_str(&data)
-            .map_err(|e| PyErr::new::<PyValueError, _>(format!("Error parsing JSON: {}", e)))?;
-
-        // Extract and convert JSON values
-        let vocabulary: HashSet<String> = serde_json::from_value(config.get("vocabulary").cloned().unwrap_or_default())
-            .map_err(|e| PyErr::new::<PyValueError, _>(format!("Error parsing vocabulary: {}", e)))?;
-        let merge_rules: Vec<(String, String)> = serde_json::from_value(config.get("merge_rules").cloned().unwrap_or_default())
-            .map_err(|e| PyErr::new::<PyValueError, _>(format!("Error parsing merge rules: {}", e)))?;
-        let config = serde_json::from_value(config.get("config").cloned().unwrap_or_default())
-            .unwrap_or_else(|_| TokenConfig::new());
-
-        Ok(TokenizerPy {
-            tokenizer: Tokenizer::new(vocabulary, merge_rules, config),
-        })
+        Ok(TokenizerPy { tokenizer: Tokenizer::load(config_path)? })
     }
-    
+
     #[getter]
-    fn get_vocabulary(&self) -> PyResult<HashSet<String>> {
+    fn get_vocabulary(&self) -> PyResult<Vec<String>> {
         Ok(self.tokenizer.get_vocabulary())
     }
 
@@ -142,52 +523,500 @@ impl TokenizerPy {
         Ok(self.tokenizer.get_merge_rules())
     }
 
+    #[getter]
+    fn get_mode(&self) -> String {
+        self.tokenizer.mode.to_string()
+    }
+
+    #[setter]
+    fn set_mode(&mut self, mode: &str) -> PyResult<()> {
+        self.tokenizer.set_mode(mode.parse()?);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_special_token_mode(&self) -> String {
+        self.tokenizer.special_token_mode.to_string()
+    }
+
+    #[setter]
+    fn set_special_token_mode(&mut self, mode: &str) -> PyResult<()> {
+        self.tokenizer.set_special_token_mode(mode.parse()?);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_byte_fallback(&self) -> bool {
+        self.tokenizer.byte_fallback
+    }
+
+    fn enable_byte_fallback(&mut self) -> PyResult<()> {
+        Ok(self.tokenizer.enable_byte_fallback()?)
+    }
+
+    #[getter]
+    fn get_added_tokens(&self) -> PyResult<String> {
+        to_json(&self.tokenizer.config.added_tokens)
+    }
+
+    #[pyo3(signature = (tokens, special=false, single_word=false, lstrip=false, rstrip=false, normalized=false))]
+    fn add_tokens(
+        &mut self,
+        tokens: Vec<String>,
+        special: bool,
+        single_word: bool,
+        lstrip: bool,
+        rstrip: bool,
+        normalized: bool,
+    ) -> PyResult<Vec<usize>> {
+        let tokens = tokens.iter().map(|value| AddedToken {
+            single_word,
+            lstrip,
+            rstrip,
+            normalized,
+            ..AddedToken::new(value, special)
+        }).collect();
+        Ok(self.tokenizer.add_tokens(tokens)?)
+    }
+
     fn get_token(&self, index: usize) -> PyResult<String> {
-        match self.tokenizer.get_token(index) {
-            Some(index) => Ok(index),
-            None => Err(PyErr::new::<PyValueError, _>(format!("Token not found: {}", index))),
-        }
+        Ok(self.tokenizer.get_token(index).ok_or(TokenizerError::UnknownIndex(index))?)
     }
 
     fn get_index(&self, token: &str) -> PyResult<usize> {
-        match self.tokenizer.get_index(token) {
-            Some(index) => Ok(index),
-            None => Err(PyErr::new::<PyValueError, _>(format!("Token not found: {}", token))),
-        }
+        Ok(self.tokenizer.get_index(token).ok_or_else(|| TokenizerError::UnknownToken(token.to_string()))?)
     }
 
     fn get_tokens(&self, indices: Vec<usize>) -> PyResult<Vec<String>> {
-        Ok(self.tokenizer.get_tokens(&indices))
+        Ok(self.tokenizer.get_tokens(&indices)?)
     }
-    
 
     fn get_indices(&self, tokens: Vec<String>) -> PyResult<Vec<usize>> {
-        Ok(self.tokenizer.get_indices(&tokens))
+        Ok(self.tokenizer.get_indices(&tokens)?)
     }
 
     fn tokenize(&self, text: &str) -> PyResult<Vec<usize>> {
         Ok(self.tokenizer.tokenize(text))
     }
 
+    fn encode(&self, text: &str) -> PyResult<EncodingPy> {
+        Ok(EncodingPy::from(self.tokenizer.encode(text)))
+    }
+
+    // Encode a UTF-8 file without reading it into memory at once
+    fn encode_file(&self, path: &str) -> PyResult<Vec<usize>> {
+        let file = std::fs::File::open(path).map_err(TokenizerError::from)?;
+        let ids = self.tokenizer.encode_reader(std::io::BufReader::new(file)).collect::<std::io::Result<_>>();
+        Ok(ids.map_err(TokenizerError::from)?)
+    }
+
+    fn encode_pair(&self, first: &str, second: &str) -> PyResult<EncodingPy> {
+        Ok(EncodingPy::from(self.tokenizer.encode_pair(first, second)))
+    }
+
+    #[pyo3(signature = (text, p, seed=0))]
+    fn encode_with_dropout(&self, text: &str, p: f64, seed: u64) -> PyResult<EncodingPy> {
+        Ok(EncodingPy::from(self.tokenizer.encode_with_dropout(text, p, seed)?))
+    }
+
+    #[pyo3(signature = (text, n, p, seed=0))]
+    fn sample_encodings(&self, text: &str, n: usize, p: f64, seed: u64) -> PyResult<Vec<EncodingPy>> {
+        Ok(self.tokenizer.sample_encodings(text, n, p, seed)?.into_iter().map(EncodingPy::from).collect())
+    }
+
+    #[pyo3(signature = (text, n, p=0.1, seed=0))]
+    fn encode_nbest(&self, text: &str, n: usize, p: f64, seed: u64) -> PyResult<Vec<EncodingPy>> {
+        Ok(self.tokenizer.encode_nbest(text, n, p, seed)?.into_iter().map(EncodingPy::from).collect())
+    }
+
+    fn count_tokens(&self, text: &str) -> usize {
+        self.tokenizer.count_tokens(text)
+    }
+
+    fn truncate_to_tokens<'a>(&self, text: &'a str, n: usize) -> &'a str {
+        self.tokenizer.truncate_to_tokens(text, n)
+    }
+
+    fn count_tokens_batch(&self, texts: Vec<String>) -> Vec<usize> {
+        self.tokenizer.count_tokens_batch(&texts)
+    }
+
+    fn truncate_to_tokens_batch(&self, texts: Vec<String>, n: usize) -> Vec<String> {
+        self.tokenizer.truncate_to_tokens_batch(&texts, n).into_iter().map(str::to_string).collect()
+    }
+
+    #[pyo3(signature = (text, max_length, pair=None, strategy="longest_first", stride=0))]
+    fn encode_truncated(
+        &self,
+        text: &str,
+        max_length: usize,
+        pair: Option<&str>,
+        strategy: &str,
+        stride: usize,
+    ) -> PyResult<EncodingPy> {
+        let strategy: TruncationStrategy = strategy.parse()?;
+        let truncation = Truncation { max_length, strategy, stride };
+        Ok(EncodingPy::from(self.tokenizer.encode_truncated(text, pair, &truncation)?))
+    }
+
+    // `padding` is a fixed length, or None to pad to the longest text
+    #[pyo3(signature = (texts, padding=None, padding_side="right", max_length=None))]
+    fn encode_batch(
+        &self,
+        texts: Vec<String>,
+        padding: Option<usize>,
+        padding_side: &str,
+        max_length: Option<usize>,
+    ) -> PyResult<BatchEncodingPy> {
+        let padding_side = match padding_side {
+            "left" => PaddingSide::Left,
+            "right" => PaddingSide::Right,
+            _ => return Err(PyValueError::new_err(format!("Unknown padding side: {}", padding_side))),
+        };
+        let options = BatchOptions {
+            padding: padding.map_or(Padding::Longest, Padding::Fixed),
+            padding_side,
+            max_length,
+        };
+        Ok(BatchEncodingPy::from(self.tokenizer.encode_batch(&texts, &options)?))
+    }
+
     fn detokenize(&self, indices: Vec<usize>) -> PyResult<String> {
         Ok(self.tokenizer.detokenize(&indices))
     }
 
+    #[getter]
+    fn get_cased(&self) -> bool {
+        self.tokenizer.is_cased()
+    }
+
+    #[getter]
+    fn get_normalizer(&self) -> PyResult<String> {
+        to_json(&self.tokenizer.normalizer)
+    }
+
+    #[setter]
+    fn set_normalizer(&mut self, normalizer: &str) -> PyResult<()> {
+        self.tokenizer.set_normalizer(from_json(normalizer)?);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_pre_tokenizer(&self) -> PyResult<String> {
+        to_json(&self.tokenizer.pre_tokenizer)
+    }
+
+    #[setter]
+    fn set_pre_tokenizer(&mut self, pre_tokenizer: &str) -> PyResult<()> {
+        self.tokenizer.set_pre_tokenizer(from_json(pre_tokenizer)?);
+        Ok(())
+    }
+
+    #[getter]
+    fn get_post_processor(&self) -> PyResult<String> {
+        to_json(&self.tokenizer.post_processor)
+    }
+
+    #[setter]
+    fn set_post_processor(&mut self, post_processor: &str) -> PyResult<()> {
+        Ok(self.tokenizer.set_post_processor(from_json(post_processor)?)?)
+    }
+
+    #[getter]
+    fn get_decoder(&self) -> PyResult<String> {
+        to_json(&self.tokenizer.decoder)
+    }
+
+    #[setter]
+    fn set_decoder(&mut self, decoder: &str) -> PyResult<()> {
+        self.tokenizer.set_decoder(from_json(decoder)?);
+        Ok(())
+    }
+
+    fn pre_tokenize(&self, text: &str) -> Vec<String> {
+        self.tokenizer.pre_tokenize(text).into_iter().map(|(_, piece)| piece.to_string()).collect()
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        self.tokenizer.normalize(text)
+    }
+
+    fn save(&self, path: &str) -> PyResult<()> {
+        Ok(self.tokenizer.save(path)?)
+    }
+
+    // Export as a HuggingFace `tokenizer.json`
+    fn save_huggingface(&self, path: &str) -> PyResult<()> {
+        Ok(self.tokenizer.save_huggingface(path)?)
+    }
+
     #[staticmethod]
-    fn clean_text(text: &str) -> String {
-        Tokenizer::clean_text(text)
+    fn load(path: &str) -> PyResult<Self> {
+        Ok(TokenizerPy { tokenizer: Tokenizer::load(path)? })
+    }
+
+    // Import a HuggingFace `tokenizer.json` with a BPE model
+    #[staticmethod]
+    fn load_huggingface(path: &str) -> PyResult<Self> {
+        Ok(TokenizerPy { tokenizer: Tokenizer::load_huggingface(path)? })
+    }
+
+    // Load a `.tiktoken` rank file with the split regex and special tokens of its encoding
+    #[staticmethod]
+    #[pyo3(signature = (path, pattern, special_tokens=HashMap::new()))]
+    fn load_tiktoken(path: &str, pattern: &str, special_tokens: HashMap<String, usize>) -> PyResult<Self> {
+        Ok(TokenizerPy { tokenizer: Tokenizer::load_tiktoken(path, pattern, &special_tokens)? })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pyclass]
+#[derive(Clone)]
+struct EncodingPy {
+    #[pyo3(get)]
+    ids: Vec<usize>,
+    #[pyo3(get)]
+    tokens: Vec<String>,
+    #[pyo3(get)]
+    offsets: Vec<(usize, usize)>,
+    #[pyo3(get)]
+    char_offsets: Vec<(usize, usize)>,
+    #[pyo3(get)]
+    special_tokens_mask: Vec<u8>,
+    #[pyo3(get)]
+    attention_mask: Vec<u8>,
+    #[pyo3(get)]
+    word_ids: Vec<Option<usize>>,
+    #[pyo3(get)]
+    type_ids: Vec<u8>,
+    #[pyo3(get)]
+    overflowing: Vec<EncodingPy>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<Encoding> for EncodingPy {
+    fn from(encoding: Encoding) -> Self {
+        EncodingPy {
+            ids: encoding.ids,
+            tokens: encoding.tokens,
+            offsets: encoding.offsets,
+            char_offsets: encoding.char_offsets,
+            special_tokens_mask: encoding.special_tokens_mask,
+            attention_mask: encoding.attention_mask,
+            word_ids: encoding.word_ids,
+            type_ids: encoding.type_ids,
+            overflowing: encoding.overflowing.into_iter().map(EncodingPy::from).collect(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pymethods]
+impl EncodingPy {
+    fn __len__(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Encoding(num_tokens={}, tokens={:?})", self.ids.len(), self.tokens)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pyclass]
+struct BatchEncodingPy {
+    #[pyo3(get)]
+    ids: Vec<Vec<usize>>,
+    #[pyo3(get)]
+    attention_mask: Vec<Vec<u8>>,
+    #[pyo3(get)]
+    encodings: Vec<EncodingPy>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<BatchEncoding> for BatchEncodingPy {
+    fn from(batch: BatchEncoding) -> Self {
+        BatchEncodingPy {
+            ids: batch.ids,
+            attention_mask: batch.attention_mask,
+            encodings: batch.encodings.into_iter().map(EncodingPy::from).collect(),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pymethods]
+impl BatchEncodingPy {
+    fn __len__(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn __repr__(&self) -> String {
+        let length = self.ids.first().map_or(0, Vec::len);
+        format!("BatchEncoding(num_texts={}, length={})", self.ids.len(), length)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pyclass]
+#[derive(Default)]
+struct DecodeStreamPy {
+    stream: DecodeStream,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pymethods]
+impl DecodeStreamPy {
+    #[new]
+    fn new() -> Self {
+        DecodeStreamPy::default()
+    }
+
+    // Newly completed text, or None while a character is incomplete
+    fn step(&mut self, tokenizer: &TokenizerPy, index: usize) -> Option<String> {
+        self.stream.step(&tokenizer.tokenizer, index)
+    }
+
+    fn finish(&mut self, tokenizer: &TokenizerPy) -> String {
+        self.stream.finish(&tokenizer.tokenizer)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pyclass]
+struct UnigramPy {
+    unigram: Unigram,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pymethods]
+impl UnigramPy {
+    #[new]
+    fn new(config_path: &str) -> PyResult<Self> {
+        Ok(UnigramPy { unigram: Unigram::load(config_path)? })
+    }
+
+    // Train on a corpus and save the model to `output_path`
+    #[staticmethod]
+    #[pyo3(signature = (source, vocab_size, output_path, byte_fallback=false))]
+    fn train(source: &str, vocab_size: usize, output_path: &str, byte_fallback: bool) -> PyResult<Self> {
+        let options = TrainOptions { byte_fallback, ..Default::default() };
+        Ok(UnigramPy { unigram: Unigram::train(source, vocab_size, output_path, options)? })
+    }
+
+    #[getter]
+    fn get_vocabulary(&self) -> Vec<String> {
+        self.unigram.get_vocabulary()
+    }
+
+    #[getter]
+    fn get_scores(&self) -> Vec<f64> {
+        self.unigram.scores.clone()
+    }
+
+    fn get_token(&self, index: usize) -> PyResult<String> {
+        Ok(self.unigram.get_token(index).ok_or(TokenizerError::UnknownIndex(index))?)
+    }
+
+    fn get_index(&self, token: &str) -> PyResult<usize> {
+        Ok(self.unigram.get_index(token).ok_or_else(|| TokenizerError::UnknownToken(token.to_string()))?)
+    }
+
+    fn get_tokens(&self, indices: Vec<usize>) -> PyResult<Vec<String>> {
+        Ok(self.unigram.get_tokens(&indices)?)
+    }
+
+    fn get_indices(&self, tokens: Vec<String>) -> PyResult<Vec<usize>> {
+        Ok(self.unigram.get_indices(&tokens)?)
+    }
+
+    fn tokenize(&self, text: &str) -> Vec<usize> {
+        self.unigram.tokenize(text)
+    }
+
+    fn detokenize(&self, indices: Vec<usize>) -> String {
+        self.unigram.detokenize(&indices)
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        self.unigram.normalize(text)
+    }
+
+    fn save(&self, path: &str) -> PyResult<()> {
+        Ok(self.unigram.save(path)?)
+    }
+
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<Self> {
+        Ok(UnigramPy { unigram: Unigram::load(path)? })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pyclass]
+struct WordPiecePy {
+    wordpiece: WordPiece,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[pymethods]
+impl WordPiecePy {
+    #[new]
+    fn new(config_path: &str) -> PyResult<Self> {
+        Ok(WordPiecePy { wordpiece: WordPiece::load(config_path)? })
+    }
+
+    // Train on a corpus and save the model to `output_path`
+    #[staticmethod]
+    fn train(source: &str, iterations: usize, output_path: &str) -> PyResult<Self> {
+        Ok(WordPiecePy { wordpiece: WordPiece::train(source, iterations, output_path, TrainOptions::default())? })
+    }
+
+    #[getter]
+    fn get_vocabulary(&self) -> Vec<String> {
+        self.wordpiece.get_vocabulary()
+    }
+
+    #[getter]
+    fn get_continuing_subword_prefix(&self) -> String {
+        self.wordpiece.continuing_subword_prefix.clone()
+    }
+
+    fn get_token(&self, index: usize) -> PyResult<String> {
+        Ok(self.wordpiece.get_token(index).ok_or(TokenizerError::UnknownIndex(index))?)
+    }
+
+    fn get_index(&self, token: &str) -> PyResult<usize> {
+        Ok(self.wordpiece.get_index(token).ok_or_else(|| TokenizerError::UnknownToken(token.to_string()))?)
+    }
+
+    fn get_tokens(&self, indices: Vec<usize>) -> PyResult<Vec<String>> {
+        Ok(self.wordpiece.get_tokens(&indices)?)
+    }
+
+    fn get_indices(&self, tokens: Vec<String>) -> PyResult<Vec<usize>> {
+        Ok(self.wordpiece.get_indices(&tokens)?)
+    }
+
+    fn tokenize(&self, text: &str) -> Vec<usize> {
+        self.wordpiece.tokenize(text)
+    }
+
+    fn detokenize(&self, indices: Vec<usize>) -> String {
+        self.wordpiece.detokenize(&indices)
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        self.wordpiece.normalize(text)
     }
 
     fn save(&self, path: &str) -> PyResult<()> {
-        self.tokenizer.save(path)
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("Error saving tokenizer: {}", e)))
+        Ok(self.wordpiece.save(path)?)
     }
 
     #[staticmethod]
     fn load(path: &str) -> PyResult<Self> {
-        let tokenizer = Tokenizer::load(path)
-            .map_err(|e| PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("Error loading tokenizer: {}", e)))?;
-        Ok(TokenizerPy { tokenizer })
+        Ok(WordPiecePy { wordpiece: WordPiece::load(path)? })
     }
 }
 
@@ -195,5 +1024,10 @@ impl TokenizerPy {
 #[pymodule]
 fn rust_bpe(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<TokenizerPy>()?;
+    m.add_class::<EncodingPy>()?;
+    m.add_class::<BatchEncodingPy>()?;
+    m.add_class::<DecodeStreamPy>()?;
+    m.add_class::<UnigramPy>()?;
+    m.add_class::<WordPiecePy>()?;
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,146 @@
+use serde::{Serialize, Deserialize};
+
+use crate::tokenizer::{SpecialTokenMode, TokenConfig};
+use crate::tokenizer::token_config::Token;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AddedToken {
+    pub value: String,
+    // Assigned when the token is added to a tokenizer
+    #[serde(default)]
+    pub index: usize,
+    // Special tokens are masked as control tokens and encoded as text in `Plain` mode
+    #[serde(default)]
+    pub special: bool,
+    // Only match when not inside a longer word
+    #[serde(default)]
+    pub single_word: bool,
+    // Absorb whitespace to the left or right of a match
+    #[serde(default)]
+    pub lstrip: bool,
+    #[serde(default)]
+    pub rstrip: bool,
+    // Match in the normalized text rather than the raw input
+    #[serde(default)]
+    pub normalized: bool,
+}
+
+impl AddedToken {
+    // Matches the raw input whole by default
+    pub fn new(value: &str, special: bool) -> Self {
+        AddedToken {
+            value: value.to_string(),
+            index: 0,
+            special,
+            single_word: false,
+            lstrip: false,
+            rstrip: false,
+            normalized: false,
+        }
+    }
+
+    pub fn token(&self) -> Token {
+        Token { value: self.value.clone(), index: self.index }
+    }
+}
+
+// A string to match whole in the input
+pub struct Matcher<'a> {
+    pub pattern: &'a str,
+    pub index: usize,
+    pub single_word: bool,
+    pub lstrip: bool,
+    pub rstrip: bool,
+}
+
+impl<'a> Matcher<'a> {
+    pub fn plain(token: &'a Token) -> Self {
+        Matcher { pattern: &token.value, index: token.index, single_word: false, lstrip: false, rstrip: false }
+    }
+
+    pub fn added(pattern: &'a str, token: &AddedToken) -> Self {
+        Matcher {
+            pattern,
+            index: token.index,
+            single_word: token.single_word,
+            lstrip: token.lstrip,
+            rstrip: token.rstrip,
+        }
+    }
+
+    // First occurrence at or after `from` that respects word boundaries
+    fn find(&self, text: &str, from: usize) -> Option<usize> {
+        if self.pattern.is_empty() {
+            return None;
+        }
+        text[from..].match_indices(self.pattern).map(|(i, _)| from + i).find(|&start| {
+            let end = start + self.pattern.len();
+            let before = text[..start].chars().next_back().is_some_and(is_word_char);
+            let after = text[end..].chars().next().is_some_and(is_word_char);
+            !self.single_word || (!before && !after)
+        })
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Added tokens matched before or after normalization, with the pattern of each. Special added
+// tokens take part only when special tokens are matched
+pub fn added_matchers<'a>(tokens: &'a [AddedToken], patterns: &'a [String], normalized: bool, mode: SpecialTokenMode) -> Vec<Matcher<'a>> {
+    let matching = mode == SpecialTokenMode::Match;
+    tokens.iter().zip(patterns.iter())
+        .filter(|(token, _)| token.normalized == normalized && (matching || !token.special))
+        .map(|(token, pattern)| Matcher::added(pattern, token))
+        .collect()
+}
+
+// Split raw input into (byte offset, segment, token index) at control tokens and added tokens
+// matched before normalization. Control tokens and the unknown token are left as text in `Plain` mode
+pub fn split_special_tokens<'a>(text: &'a str, config: &TokenConfig, mode: SpecialTokenMode) -> Vec<(usize, &'a str, Option<usize>)> {
+    let patterns: Vec<String> = config.added_tokens.iter().map(|token| token.value.clone()).collect();
+    let mut matchers = added_matchers(&config.added_tokens, &patterns, false, mode);
+    if mode == SpecialTokenMode::Match {
+        let control = config.control_tokens().into_iter().chain([&config.unknown]);
+        matchers.extend(control.map(Matcher::plain));
+    }
+
+    let mut segments = Vec::new();
+    let mut last = 0;
+    for (start, end, index) in find_matches(text, &matchers) {
+        if start > last {
+            segments.push((last, &text[last..start], None));
+        }
+        segments.push((start, &text[start..end], Some(index)));
+        last = end;
+    }
+    if last < text.len() || segments.is_empty() {
+        segments.push((last, &text[last..], None));
+    }
+    segments
+}
+
+// Non-overlapping (start, end, index) matches, leftmost and then longest first. Stripped
+// whitespace is included in the match range
+pub fn find_matches(text: &str, matchers: &[Matcher]) -> Vec<(usize, usize, usize)> {
+    let mut matches = Vec::new();
+    let mut last = 0;
+    loop {
+        let found = matchers.iter()
+            .filter_map(|matcher| matcher.find(text, last).map(|start| (start, matcher)))
+            .min_by_key(|&(start, matcher)| (start, std::cmp::Reverse(matcher.pattern.len())));
+        let Some((mut start, matcher)) = found else { break };
+        let mut end = start + matcher.pattern.len();
+        if matcher.lstrip {
+            start = last + text[last..start].trim_end().len();
+        }
+        if matcher.rstrip {
+            end = text.len() - text[end..].trim_start().len();
+        }
+        matches.push((start, end, matcher.index));
+        last = end;
+    }
+    matches
+}
@@ -0,0 +1,37 @@
+// The byte token in the `<0xNN>` form used for characters missing from the vocabulary
+pub fn byte_token(byte: u8) -> String {
+    format!("<0x{:02X}>", byte)
+}
+
+pub fn byte_tokens() -> Vec<String> {
+    (0..=u8::MAX).map(byte_token).collect()
+}
+
+pub fn parse_byte_token(token: &str) -> Option<u8> {
+    let hex = token.strip_prefix("<0x")?.strip_suffix('>')?;
+    if hex.len() != 2 {
+        return None;
+    }
+    u8::from_str_radix(hex, 16).ok()
+}
+
+// A token made of one or more byte tokens, such as `<0xE2><0x80>`
+pub fn parse_byte_tokens(token: &str) -> Option<Vec<u8>> {
+    if token.is_empty() || !token.len().is_multiple_of(6) {
+        return None;
+    }
+    (0..token.len()).step_by(6).map(|i| token.get(i..i + 6).and_then(parse_byte_token)).collect()
+}
+
+// The bytes a token stands for - a run of byte tokens is its bytes, any other token its UTF-8 text
+pub fn token_bytes(token: &str) -> Vec<u8> {
+    parse_byte_tokens(token).unwrap_or_else(|| token.as_bytes().to_vec())
+}
+
+// The text of a token's bytes - a token that is not valid UTF-8 on its own becomes a run of byte tokens
+pub fn bytes_token(bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(error) => error.into_bytes().into_iter().map(byte_token).collect(),
+    }
+}
@@ -0,0 +1,45 @@
+use crate::tokenizer::{Model, Tokenizer};
+
+
+// Decodes ids one at a time, yielding text as soon as it is settled. Each step decodes the new id
+// together with the previous one, so decoders that look at neighbouring tokens behave as in
+// `detokenize`. Text ending in an incomplete character or in whitespace, which an eos or a
+// clean-up may still change, is held back until the next id
+#[derive(Debug, Clone, Default)]
+pub struct DecodeStream {
+    ids: Vec<usize>,
+    // Decoded text of the context id kept from the previous step, already yielded
+    prefix: String,
+}
+
+impl DecodeStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add one id, returning any newly settled text
+    pub fn step(&mut self, tokenizer: &Tokenizer, index: usize) -> Option<String> {
+        self.ids.push(index);
+        let text = tokenizer.detokenize(&self.ids);
+        let settled = text.len() > self.prefix.len()
+            && text.starts_with(&self.prefix)
+            && !text.ends_with(|c: char| c == '\u{FFFD}' || c.is_whitespace());
+        if !settled {
+            return None;
+        }
+
+        let new_text = text[self.prefix.len()..].to_string();
+        // Keep the last id as context for the next step
+        self.ids.drain(..self.ids.len() - 1);
+        self.prefix = tokenizer.detokenize(&self.ids);
+        Some(new_text)
+    }
+
+    // Flush whatever is still held back
+    pub fn finish(&mut self, tokenizer: &Tokenizer) -> String {
+        let text = tokenizer.detokenize(&self.ids);
+        let rest = text.strip_prefix(self.prefix.as_str()).unwrap_or(&text).to_string();
+        *self = Self::default();
+        rest
+    }
+}
@@ -0,0 +1,116 @@
+use serde::{Serialize, Deserialize};
+
+use crate::tokenizer::TokenConfig;
+use crate::tokenizer::bytes::parse_byte_tokens;
+
+
+pub trait Decoder {
+    // Turn the token strings of a sequence into output pieces that are concatenated
+    fn decode_chain(&self, tokens: Vec<String>, config: &TokenConfig) -> Vec<String>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum DecoderStep {
+    // Replace eos with a space unless the next token is special, as `detokenize` always did
+    EosAsSpace,
+    // Rebuild characters from runs of `<0xNN>` byte tokens, including tokens that join several
+    ByteFallback,
+    // Turn a word-boundary marker such as "▁" or "Ġ" into a space, dropping it at the very start
+    WordBoundary { marker: String },
+    // Strip a continuation prefix such as "##", and put a space before every other token but the first
+    WordPiece { prefix: String },
+    // Join the tokens and remove spaces before punctuation and English contractions
+    CleanUp,
+}
+
+impl Decoder for DecoderStep {
+    fn decode_chain(&self, tokens: Vec<String>, config: &TokenConfig) -> Vec<String> {
+        match self {
+            DecoderStep::EosAsSpace => {
+                let mut output = Vec::with_capacity(tokens.len());
+                for (i, token) in tokens.iter().enumerate() {
+                    if !config.is_eos(token) {
+                        output.push(token.clone());
+                    } else if !tokens.get(i + 1).is_some_and(|next| config.is_special_token(next)) {
+                        output.push(" ".to_string());
+                    }
+                }
+                output
+            }
+            DecoderStep::ByteFallback => {
+                let mut output = Vec::with_capacity(tokens.len());
+                let mut bytes = Vec::new();
+                for token in tokens {
+                    match parse_byte_tokens(&token) {
+                        Some(token_bytes) => bytes.extend(token_bytes),
+                        None => {
+                            if !bytes.is_empty() {
+                                output.push(String::from_utf8_lossy(&bytes).into_owned());
+                                bytes.clear();
+                            }
+                            output.push(token);
+                        }
+                    }
+                }
+                if !bytes.is_empty() {
+                    output.push(String::from_utf8_lossy(&bytes).into_owned());
+                }
+                output
+            }
+            DecoderStep::WordBoundary { marker } => {
+                tokens.into_iter().enumerate().map(|(i, token)| {
+                    let token = token.replace(marker.as_str(), " ");
+                    match token.strip_prefix(' ') {
+                        Some(stripped) if i == 0 => stripped.to_string(),
+                        _ => token,
+                    }
+                }).collect()
+            }
+            DecoderStep::WordPiece { prefix } => {
+                tokens.into_iter().enumerate().map(|(i, token)| match token.strip_prefix(prefix.as_str()) {
+                    Some(rest) => rest.to_string(),
+                    None if i > 0 => format!(" {}", token),
+                    None => token,
+                }).collect()
+            }
+            DecoderStep::CleanUp => {
+                let mut text = tokens.concat();
+                for (from, to) in [
+                    (" .", "."), (" ?", "?"), (" !", "!"), (" ,", ","), (" ' ", "'"),
+                    (" n't", "n't"), (" 'm", "'m"), (" 's", "'s"), (" 've", "'ve"), (" 're", "'re"),
+                ] {
+                    text = text.replace(from, to);
+                }
+                vec![text]
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DecoderPipeline {
+    // Drop control tokens, including special added tokens, before the steps run
+    #[serde(default)]
+    pub skip_special_tokens: bool,
+    pub steps: Vec<DecoderStep>,
+}
+
+impl DecoderPipeline {
+    pub fn new(steps: Vec<DecoderStep>) -> Self {
+        DecoderPipeline { skip_special_tokens: false, steps }
+    }
+}
+
+impl Default for DecoderPipeline {
+    // Original behaviour
+    fn default() -> Self {
+        Self::new(vec![DecoderStep::EosAsSpace, DecoderStep::ByteFallback])
+    }
+}
+
+impl Decoder for DecoderPipeline {
+    fn decode_chain(&self, tokens: Vec<String>, config: &TokenConfig) -> Vec<String> {
+        self.steps.iter().fold(tokens, |tokens, step| step.decode_chain(tokens, config))
+    }
+}
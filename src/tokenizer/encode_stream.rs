@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use unicode_normalization::char::is_combining_mark;
+
+use crate::tokenizer::{Normalizer, PreTokenizer, Tokenizer};
+use crate::tokenizer::model::tokenize_from;
+
+
+const READ_CHUNK_SIZE: usize = 1 << 16;
+// Buffered text is cut regardless once it grows past this many bytes
+const MAX_BUFFER_SIZE: usize = 1 << 18;
+
+// Lazily encodes text arriving in chunks. Buffered text is only encoded up to a line break followed
+// by the start of a word, and only where normalization, pre-tokenization and the vocabulary cannot
+// join text across the cut, so the ids match `tokenize` on the whole text. A buffer that grows past
+// `MAX_BUFFER_SIZE` without such a cut is cut at the start of its last pre-tokenized piece or word,
+// where the ids may differ from `tokenize`
+pub struct EncodeStream<'a, I> {
+    tokenizer: &'a Tokenizer,
+    chunks: I,
+    buffer: String,
+    // Bytes of `buffer` already searched for a cut
+    searched: usize,
+    // Whether `buffer` starts partway through text that was already encoded
+    continued: bool,
+    ready: VecDeque<usize>,
+    done: bool,
+}
+
+impl<'a, I, E> EncodeStream<'a, I>
+where
+    I: Iterator<Item = Result<String, E>>,
+{
+    pub fn new(tokenizer: &'a Tokenizer, chunks: I) -> Self {
+        EncodeStream {
+            tokenizer,
+            chunks,
+            buffer: String::new(),
+            searched: 0,
+            continued: false,
+            ready: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    // Last position after a '\n' where the next char begins a word and nothing joins across
+    fn find_cut(&self) -> Option<usize> {
+        if !self.tokenizer.normalizer.cuts_at_line_breaks() {
+            return None;
+        }
+        // Recheck a line break at the end of the previous chunk
+        let mut start = self.searched.saturating_sub(1);
+        while !self.buffer.is_char_boundary(start) {
+            start -= 1;
+        }
+        let positions: Vec<usize> = self.buffer[start..].match_indices('\n')
+            .map(|(i, _)| start + i + 1)
+            .filter(|&position| self.buffer[position..].chars().next().is_some_and(starts_word))
+            .collect();
+        if positions.is_empty() {
+            return None;
+        }
+
+        let boundaries = self.boundaries(true);
+        positions.into_iter().rev().find(|position| boundaries.binary_search(position).is_ok())
+    }
+
+    // Cut an oversized buffer at the start of its last pre-tokenized piece, or else before its last
+    // word or char
+    fn forced_cut(&self) -> Option<usize> {
+        if !self.tokenizer.pre_tokenizer.is_empty() {
+            if let Some(&last) = self.boundaries(true).last() {
+                return Some(last);
+            }
+        }
+        let boundaries = self.boundaries(false);
+        let around = |position: usize| {
+            let prev = self.buffer[..position].chars().next_back().unwrap_or(' ');
+            (prev, self.buffer[position..].chars().next().unwrap_or(' '))
+        };
+        boundaries.iter().rev()
+            .find(|&&position| matches!(around(position), (prev, next) if prev.is_whitespace() && !next.is_whitespace()))
+            .or_else(|| boundaries.iter().rev().find(|&&position| !is_combining_mark(around(position).1)))
+            .copied()
+    }
+
+    // Sorted positions where the buffer could be cut without splitting a special token: the starts
+    // of pre-tokenized pieces when `pieces` is set and there is a pre-tokenizer, otherwise every char
+    // of plain text. The last piece is left whole since more text could extend it, and positions
+    // where a token that strips whitespace on its left may yet begin are left out
+    fn boundaries(&self, pieces: bool) -> Vec<usize> {
+        let tokenizer = self.tokenizer;
+        let mut boundaries = Vec::new();
+        for (offset, segment, special) in tokenizer.split_special_tokens(&self.buffer) {
+            boundaries.push(offset);
+            if special.is_some() {
+                continue;
+            }
+            if !pieces || tokenizer.pre_tokenizer.is_empty() {
+                boundaries.extend(segment.char_indices().skip(1).map(|(i, _)| offset + i));
+                continue;
+            }
+            let normalized = if self.continued && offset == 0 {
+                tokenizer.normalizer.continued().normalize_aligned(segment)
+            } else {
+                tokenizer.normalizer.normalize_aligned(segment)
+            };
+            let starts = tokenizer.pre_tokenizer.pre_tokenize(&normalized.normalized).into_iter()
+                .skip(1)
+                .map(|(start, _)| offset + normalized.original_range(start, start).0);
+            boundaries.extend(starts);
+        }
+        boundaries.pop();
+
+        let stripped: Vec<&str> = tokenizer.config.added_tokens.iter()
+            .filter(|token| token.lstrip)
+            .map(|token| token.value.as_str())
+            .collect();
+        boundaries.retain(|&position| {
+            let rest = &self.buffer[position..];
+            position > 0 && !stripped.iter().any(|value| value.starts_with(rest) || rest.starts_with(value))
+        });
+        boundaries.dedup();
+        boundaries
+    }
+
+    // Encode the buffer up to `cut` and keep the rest
+    fn encode_until(&mut self, cut: usize) {
+        self.ready.extend(tokenize_from(self.tokenizer, &self.buffer[..cut], self.continued));
+        let segments = self.tokenizer.split_special_tokens(&self.buffer[..cut]);
+        self.continued = segments.last().is_some_and(|&(_, _, special)| special.is_none());
+        self.buffer.drain(..cut);
+    }
+}
+
+// Letters, digits and ASCII punctuation keep their place through every normalizer step
+fn starts_word(c: char) -> bool {
+    (c.is_alphanumeric() || c.is_ascii_punctuation()) && !is_combining_mark(c)
+}
+
+impl<I, E> Iterator for EncodeStream<'_, I>
+where
+    I: Iterator<Item = Result<String, E>>,
+{
+    type Item = Result<usize, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.ready.is_empty() && !self.done {
+            match self.chunks.next() {
+                Some(Ok(chunk)) => {
+                    self.buffer.push_str(&chunk);
+                    let cut = self.find_cut().or_else(|| {
+                        (self.buffer.len() > MAX_BUFFER_SIZE).then(|| self.forced_cut()).flatten()
+                    });
+                    if let Some(cut) = cut {
+                        self.encode_until(cut);
+                    }
+                    self.searched = self.buffer.len();
+                }
+                Some(Err(error)) => return Some(Err(error)),
+                None => {
+                    self.done = true;
+                    if !self.buffer.is_empty() {
+                        let end = self.buffer.len();
+                        self.encode_until(end);
+                    }
+                }
+            }
+        }
+        self.ready.pop_front().map(Ok)
+    }
+}
+
+// Reads UTF-8 text in blocks, holding back a character split across reads
+pub struct ReadChunks<R> {
+    reader: R,
+    pending: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> ReadChunks<R> {
+    pub fn new(reader: R) -> Self {
+        ReadChunks { reader, pending: Vec::new(), done: false }
+    }
+}
+
+impl<R: Read> Iterator for ReadChunks<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut block = vec![0; READ_CHUNK_SIZE];
+        let read = match self.reader.read(&mut block) {
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => return Some(Ok(String::new())),
+            Err(error) => {
+                self.done = true;
+                return Some(Err(error));
+            }
+        };
+        if read == 0 {
+            self.done = true;
+            if self.pending.is_empty() {
+                return None;
+            }
+            return Some(Err(io::Error::new(io::ErrorKind::InvalidData, "stream ends inside a UTF-8 character")));
+        }
+
+        self.pending.extend_from_slice(&block[..read]);
+        let complete = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            Err(error) => {
+                self.done = true;
+                return Some(Err(io::Error::new(io::ErrorKind::InvalidData, error)));
+            }
+        };
+        let rest = self.pending.split_off(complete);
+        let text = std::mem::replace(&mut self.pending, rest);
+        Some(Ok(String::from_utf8(text).expect("validated above")))
+    }
+}
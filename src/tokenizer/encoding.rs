@@ -0,0 +1,225 @@
+use serde::{Serialize, Deserialize};
+
+use crate::tokenizer::token_config::Token;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Encoding {
+    pub ids: Vec<usize>,
+    pub tokens: Vec<String>,
+    // Byte offsets of each token into the original, un-normalized input
+    pub offsets: Vec<(usize, usize)>,
+    // Char offsets of each token into the original input
+    pub char_offsets: Vec<(usize, usize)>,
+    pub special_tokens_mask: Vec<u8>,
+    pub attention_mask: Vec<u8>,
+    // Index of the word each token belongs to, `None` for special and whitespace-only tokens
+    pub word_ids: Vec<Option<usize>>,
+    // Segment of each token - 0 for the first input of a pair, as set by the post-processor template
+    #[serde(default)]
+    pub type_ids: Vec<u8>,
+    // Further windows of a truncated input, each wrapped in its own boundary tokens
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overflowing: Vec<Encoding>,
+}
+
+impl Encoding {
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    // Single special token that does not come from the input text
+    pub fn special(token: &Token) -> Self {
+        Encoding {
+            ids: vec![token.index],
+            tokens: vec![token.value.clone()],
+            offsets: vec![(0, 0)],
+            char_offsets: vec![(0, 0)],
+            special_tokens_mask: vec![1],
+            attention_mask: vec![1],
+            word_ids: vec![None],
+            type_ids: vec![0],
+            overflowing: Vec::new(),
+        }
+    }
+
+    // Tokens `start..end` of this encoding, without its overflowing windows
+    pub fn slice(&self, start: usize, end: usize) -> Self {
+        Encoding {
+            ids: self.ids[start..end].to_vec(),
+            tokens: self.tokens[start..end].to_vec(),
+            offsets: self.offsets[start..end].to_vec(),
+            char_offsets: self.char_offsets[start..end].to_vec(),
+            special_tokens_mask: self.special_tokens_mask[start..end].to_vec(),
+            attention_mask: self.attention_mask[start..end].to_vec(),
+            word_ids: self.word_ids[start..end].to_vec(),
+            type_ids: self.type_ids[start..end].to_vec(),
+            overflowing: Vec::new(),
+        }
+    }
+
+    // Windows of at most `length` tokens, each sharing `stride` tokens with the previous one
+    pub fn windows(&self, length: usize, stride: usize) -> Vec<Self> {
+        let length = length.max(1);
+        let step = length.saturating_sub(stride).max(1);
+        let mut windows = Vec::new();
+        let mut start = 0;
+        loop {
+            let end = (start + length).min(self.len());
+            windows.push(self.slice(start, end));
+            if end == self.len() {
+                return windows;
+            }
+            start += step;
+        }
+    }
+
+    // Concatenate encodings in order, dropping their overflowing windows
+    pub fn concat(encodings: &[Encoding]) -> Self {
+        let mut merged = Encoding::default();
+        for encoding in encodings {
+            merged.ids.extend_from_slice(&encoding.ids);
+            merged.tokens.extend_from_slice(&encoding.tokens);
+            merged.offsets.extend_from_slice(&encoding.offsets);
+            merged.char_offsets.extend_from_slice(&encoding.char_offsets);
+            merged.special_tokens_mask.extend_from_slice(&encoding.special_tokens_mask);
+            merged.attention_mask.extend_from_slice(&encoding.attention_mask);
+            merged.word_ids.extend_from_slice(&encoding.word_ids);
+            merged.type_ids.extend_from_slice(&encoding.type_ids);
+        }
+        merged
+    }
+
+    // Keep at most `max_length` tokens from the start of the sequence
+    pub fn truncate(&mut self, max_length: usize) {
+        self.ids.truncate(max_length);
+        self.tokens.truncate(max_length);
+        self.offsets.truncate(max_length);
+        self.char_offsets.truncate(max_length);
+        self.special_tokens_mask.truncate(max_length);
+        self.attention_mask.truncate(max_length);
+        self.word_ids.truncate(max_length);
+        self.type_ids.truncate(max_length);
+    }
+
+    // Pad up to `length` tokens with the given pad token, masked out of attention
+    pub fn pad(&mut self, length: usize, pad: &Token, side: PaddingSide) {
+        if self.len() >= length {
+            return;
+        }
+        let count = length - self.len();
+        match side {
+            PaddingSide::Right => {
+                self.ids.extend(std::iter::repeat_n(pad.index, count));
+                self.tokens.extend(std::iter::repeat_n(pad.value.clone(), count));
+                self.offsets.extend(std::iter::repeat_n((0, 0), count));
+                self.char_offsets.extend(std::iter::repeat_n((0, 0), count));
+                self.special_tokens_mask.extend(std::iter::repeat_n(1, count));
+                self.attention_mask.extend(std::iter::repeat_n(0, count));
+                self.word_ids.extend(std::iter::repeat_n(None, count));
+                self.type_ids.extend(std::iter::repeat_n(0, count));
+            }
+            PaddingSide::Left => {
+                self.ids.splice(0..0, std::iter::repeat_n(pad.index, count));
+                self.tokens.splice(0..0, std::iter::repeat_n(pad.value.clone(), count));
+                self.offsets.splice(0..0, std::iter::repeat_n((0, 0), count));
+                self.char_offsets.splice(0..0, std::iter::repeat_n((0, 0), count));
+                self.special_tokens_mask.splice(0..0, std::iter::repeat_n(1, count));
+                self.attention_mask.splice(0..0, std::iter::repeat_n(0, count));
+                self.word_ids.splice(0..0, std::iter::repeat_n(None, count));
+                self.type_ids.splice(0..0, std::iter::repeat_n(0, count));
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Padding {
+    // Pad to the longest sequence in the batch
+    #[default]
+    Longest,
+    // Pad, and truncate, every sequence to this length
+    Fixed(usize),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PaddingSide {
+    Left,
+    #[default]
+    Right,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct BatchOptions {
+    pub padding: Padding,
+    pub padding_side: PaddingSide,
+    pub max_length: Option<usize>,
+}
+
+impl BatchOptions {
+    // Length every encoding of the batch is truncated to, if any
+    pub fn truncation_length(&self) -> Option<usize> {
+        match self.padding {
+            Padding::Fixed(length) => Some(self.max_length.map_or(length, |max| max.min(length))),
+            Padding::Longest => self.max_length,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BatchEncoding {
+    // Rectangular matrices with one row per input text
+    pub ids: Vec<Vec<usize>>,
+    pub attention_mask: Vec<Vec<u8>>,
+    pub encodings: Vec<Encoding>,
+}
+
+impl BatchEncoding {
+    pub fn new(mut encodings: Vec<Encoding>, options: &BatchOptions, pad: &Token) -> Self {
+        let max_length = options.truncation_length();
+        if let Some(max_length) = max_length {
+            encodings.iter_mut().for_each(|encoding| encoding.truncate(max_length));
+        }
+
+        let length = match options.padding {
+            Padding::Fixed(length) => max_length.unwrap_or(length),
+            Padding::Longest => encodings.iter().map(Encoding::len).max().unwrap_or(0),
+        };
+        encodings.iter_mut().for_each(|encoding| encoding.pad(length, pad, options.padding_side));
+
+        BatchEncoding {
+            ids: encodings.iter().map(|encoding| encoding.ids.clone()).collect(),
+            attention_mask: encodings.iter().map(|encoding| encoding.attention_mask.clone()).collect(),
+            encodings,
+        }
+    }
+}
+
+// Convert byte offsets to char offsets - partial characters (byte tokens) cover the whole char
+pub fn char_offsets(text: &str, offsets: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // starts_before[b] is the number of chars starting before byte b
+    let mut starts_before = Vec::with_capacity(text.len() + 2);
+    let mut count = 0;
+    for b in 0..=text.len() {
+        starts_before.push(count);
+        if text.is_char_boundary(b) && b < text.len() {
+            count += 1;
+        }
+    }
+    starts_before.push(count);
+
+    offsets.iter().map(|&(start, end)| {
+        if start == end {
+            (starts_before[start], starts_before[start])
+        } else {
+            (starts_before[start + 1] - 1, starts_before[end])
+        }
+    }).collect()
+}
@@ -0,0 +1,63 @@
+use std::fmt;
+use std::io;
+
+
+#[derive(Debug)]
+pub enum TokenizerError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Regex(Box<fancy_regex::Error>),
+    // An id with no token in the vocabulary
+    UnknownIndex(usize),
+    // A token string that is not in the vocabulary
+    UnknownToken(String),
+    // One of the nine fixed special tokens, or an added token, is missing from the vocabulary
+    MissingSpecialToken(String),
+    // A setting such as a mode name or a template that cannot be parsed or applied
+    InvalidConfig(String),
+}
+
+pub type Result<T> = std::result::Result<T, TokenizerError>;
+
+impl fmt::Display for TokenizerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenizerError::Io(e) => write!(f, "I/O error: {}", e),
+            TokenizerError::Json(e) => write!(f, "JSON error: {}", e),
+            TokenizerError::Regex(e) => write!(f, "Invalid regex: {}", e),
+            TokenizerError::UnknownIndex(index) => write!(f, "Index {} is not in the vocabulary", index),
+            TokenizerError::UnknownToken(token) => write!(f, "Token {:?} is not in the vocabulary", token),
+            TokenizerError::MissingSpecialToken(token) => write!(f, "Special token {:?} missing from vocabulary", token),
+            TokenizerError::InvalidConfig(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for TokenizerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TokenizerError::Io(e) => Some(e),
+            TokenizerError::Json(e) => Some(e),
+            TokenizerError::Regex(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TokenizerError {
+    fn from(error: io::Error) -> Self {
+        TokenizerError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for TokenizerError {
+    fn from(error: serde_json::Error) -> Self {
+        TokenizerError::Json(error)
+    }
+}
+
+impl From<fancy_regex::Error> for TokenizerError {
+    fn from(error: fancy_regex::Error) -> Self {
+        TokenizerError::Regex(Box::new(error))
+    }
+}
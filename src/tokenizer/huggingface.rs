@@ -0,0 +1,559 @@
+use std::collections::{HashMap, HashSet};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::tokenizer::{Model, Tokenizer, TokenConfig, EncodingMode, SpecialTokenMode};
+use crate::tokenizer::{NormalizerPipeline, NormalizerStep};
+use crate::tokenizer::{PreTokenizerPipeline, PreTokenizerStep};
+use crate::tokenizer::{DecoderPipeline, DecoderStep};
+use crate::tokenizer::{PostProcessor, Template, TemplatePiece};
+use crate::tokenizer::added_tokens::AddedToken;
+use crate::tokenizer::normalizer::Pattern;
+use crate::tokenizer::pre_tokenizer::GPT2_PATTERN;
+use crate::tokenizer::bytes::{bytes_token, token_bytes};
+use crate::tokenizer::error::{Result, TokenizerError};
+
+
+// The parts of a HuggingFace `tokenizer.json` that map to this crate
+#[derive(Deserialize)]
+struct HfTokenizer {
+    #[serde(default)]
+    added_tokens: Vec<HfAddedToken>,
+    normalizer: Option<Value>,
+    pre_tokenizer: Option<Value>,
+    post_processor: Option<Value>,
+    decoder: Option<Value>,
+    model: Value,
+}
+
+#[derive(Deserialize)]
+struct HfAddedToken {
+    id: usize,
+    content: String,
+    #[serde(default)]
+    single_word: bool,
+    #[serde(default)]
+    lstrip: bool,
+    #[serde(default)]
+    rstrip: bool,
+    #[serde(default)]
+    normalized: bool,
+    #[serde(default)]
+    special: bool,
+}
+
+#[derive(Deserialize)]
+struct HfBpe {
+    vocab: HashMap<String, usize>,
+    #[serde(default)]
+    merges: Vec<HfMerge>,
+    #[serde(default)]
+    unk_token: Option<String>,
+    #[serde(default)]
+    continuing_subword_prefix: Option<String>,
+    #[serde(default)]
+    end_of_word_suffix: Option<String>,
+    #[serde(default)]
+    byte_fallback: bool,
+}
+
+// Merges are saved as "a b" by older versions and as ["a", "b"] since
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HfMerge {
+    Pair(String, String),
+    Joined(String),
+}
+
+// Candidates for the fixed tokens, in order of preference - eos is eot unless it has its own
+const SOT: &[&str] = &["<|sot|>", "<s>", "<|startoftext|>", "<|begin_of_text|>", "<bos>", "[CLS]"];
+const EOT: &[&str] = &["<|eot|>", "</s>", "<|endoftext|>", "<|end_of_text|>", "<eos>", "[SEP]"];
+const EOS: &[&str] = &["<|eos|>"];
+const PAD: &[&str] = &["<pad>", "[PAD]", "<|padding|>"];
+const MASK: &[&str] = &["<mask>", "[MASK]"];
+const UNKNOWN: &[&str] = &["<unk>", "[UNK]"];
+
+impl Tokenizer {
+    pub fn load_huggingface(path: &str) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        Self::from_huggingface_json(&data)
+    }
+
+    // Import a BPE model saved by HuggingFace `tokenizers`. Imported tokens keep their ids, and control
+    // tokens the model lacks, such as padding and mask, get ids after them. Byte-level tokens are
+    // stored as the text they stand for, and components with no equivalent here are rejected
+    pub fn from_huggingface_json(json: &str) -> Result<Self> {
+        let file: HfTokenizer = serde_json::from_str(json)?;
+        let model_type = file.model.get("type").and_then(Value::as_str).unwrap_or("BPE");
+        if model_type != "BPE" {
+            return Err(unsupported("model", model_type));
+        }
+        let model: HfBpe = serde_json::from_value(file.model)?;
+        if model.continuing_subword_prefix.is_some() || model.end_of_word_suffix.is_some() {
+            return Err(TokenizerError::InvalidConfig("BPE subword prefixes and suffixes are not supported".to_string()));
+        }
+
+        let mut components = Components::default();
+        if let Some(normalizer) = &file.normalizer {
+            components.normalizer(normalizer)?;
+        }
+        if let Some(pre_tokenizer) = &file.pre_tokenizer {
+            components.pre_tokenizer(pre_tokenizer)?;
+        }
+        if let Some(decoder) = &file.decoder {
+            components.decoder(decoder)?;
+        }
+
+        // Added tokens are matched in the raw input, so their content is kept as written
+        let byte_level = components.byte_level;
+        let bytes = byte_level_bytes();
+        let decode = |token: String| if byte_level { from_byte_level(&token, &bytes) } else { token };
+        let size = model.vocab.values().chain(file.added_tokens.iter().map(|token| &token.id)).max().map_or(0, |&id| id + 1);
+        let mut slots = vec![None; size];
+        for (token, id) in model.vocab {
+            slots[id] = Some(decode(token));
+        }
+        for token in file.added_tokens.iter() {
+            slots[token.id] = Some(token.content.clone());
+        }
+        let mut vocabulary = slots.into_iter().enumerate()
+            .map(|(id, token)| token.ok_or_else(|| TokenizerError::InvalidConfig(format!("No token has id {}", id))))
+            .collect::<Result<Vec<String>>>()?;
+        let merge_rules = model.merges.into_iter().map(|merge| {
+            let (first, second) = match merge {
+                HfMerge::Pair(first, second) => (first, second),
+                HfMerge::Joined(joined) => match joined.split_once(' ') {
+                    Some((first, second)) => (first.to_string(), second.to_string()),
+                    None => return Err(TokenizerError::InvalidConfig(format!("Malformed merge: {}", joined))),
+                },
+            };
+            Ok((decode(first), decode(second)))
+        }).collect::<Result<Vec<_>>>()?;
+
+        let added_tokens = file.added_tokens.iter().map(|token| AddedToken {
+            value: token.content.clone(),
+            index: token.id,
+            special: token.special,
+            single_word: token.single_word,
+            lstrip: token.lstrip,
+            rstrip: token.rstrip,
+            normalized: token.normalized,
+        }).collect();
+        let config = special_tokens(&mut vocabulary, added_tokens, model.unk_token);
+        let mut tokenizer = Tokenizer::new(vocabulary, merge_rules, config)?;
+        tokenizer.mode = EncodingMode::Merge;
+        // Byte-level models fall back to the bytes missing from the vocabulary, like byte fallback
+        tokenizer.byte_fallback = model.byte_fallback || byte_level;
+        // Byte-level merges may split characters, so words are merged from single bytes
+        if byte_level {
+            tokenizer.enable_byte_level()?;
+        }
+        if tokenizer.byte_fallback && !components.decoder.contains(&DecoderStep::ByteFallback) {
+            components.decoder.insert(0, DecoderStep::ByteFallback);
+        }
+        tokenizer.normalizer = NormalizerPipeline::new(components.normalizer);
+        tokenizer.pre_tokenizer = PreTokenizerPipeline::new(components.pre_tokenizer);
+        tokenizer.decoder = DecoderPipeline::new(components.decoder);
+        if let Some(processor) = &file.post_processor {
+            tokenizer.set_post_processor(post_processor(processor)?)?;
+        }
+        Ok(tokenizer)
+    }
+
+    pub fn save_huggingface(&self, path: &str) -> Result<()> {
+        Ok(std::fs::write(path, self.to_huggingface_json()?)?)
+    }
+
+    // Export as a HuggingFace `tokenizer.json` with a BPE model. HuggingFace always applies the merge
+    // rules, as `EncodingMode::Merge` does, and decoder steps with no equivalent are left out.
+    // Byte-level models are spelled with GPT-2's stand-ins for bytes, with ByteLevel components
+    pub fn to_huggingface_json(&self) -> Result<String> {
+        let chars = byte_level_chars();
+        let spell = |token: &str| match self.byte_level {
+            true => token_bytes(token).into_iter().map(|byte| chars[byte as usize]).collect(),
+            false => token.to_string(),
+        };
+        // Control, unknown and added tokens are matched as written, so they keep their text
+        let mut vocab = serde_json::Map::new();
+        for (index, token) in self.vocabulary.iter().enumerate() {
+            let written = self.config.is_control_token(index) || self.config.is_added_token(index) || index == self.config.unknown.index;
+            vocab.entry(if written { token.clone() } else { spell(token) }).or_insert(json!(index));
+        }
+        let merges: Vec<(String, String)> = self.merge_rules.iter().map(|(first, second)| (spell(first), spell(second))).collect();
+
+        let mut pre_tokenizers: Vec<Value> = self.pre_tokenizer.steps.iter().map(pre_tokenizer_json).collect();
+        let mut decoders = decoder_json(&self.decoder.steps);
+        if self.byte_level {
+            // ByteLevel splits with the GPT-2 pattern itself, and decodes the bytes byte fallback would
+            let gpt2 = matches!(&self.pre_tokenizer.steps[..], [PreTokenizerStep::Regex { pattern }] if pattern.source == GPT2_PATTERN);
+            if gpt2 {
+                pre_tokenizers.clear();
+            }
+            pre_tokenizers.push(json!({"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true, "use_regex": gpt2}));
+            decoders.retain(|decoder| decoder["type"] != "ByteFallback");
+            decoders.insert(0, json!({"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true, "use_regex": true}));
+        }
+        let file = json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": self.huggingface_added_tokens(),
+            "normalizer": sequence("normalizers", self.normalizer.steps.iter().flat_map(normalizer_json).collect()),
+            "pre_tokenizer": sequence("pretokenizers", pre_tokenizers),
+            "post_processor": self.post_processor.as_ref().map(|processor| self.post_processor_json(processor)),
+            "decoder": sequence("decoders", decoders),
+            "model": {
+                "type": "BPE",
+                "dropout": null,
+                "unk_token": self.config.unknown.value,
+                "continuing_subword_prefix": null,
+                "end_of_word_suffix": null,
+                "fuse_unk": false,
+                "byte_fallback": self.byte_fallback && !self.byte_level,
+                "ignore_merges": false,
+                "vocab": vocab,
+                "merges": merges,
+            },
+        });
+        Ok(serde_json::to_string(&file)?)
+    }
+
+    // HuggingFace always matches added tokens in the input, so control tokens, the unknown token and
+    // special added tokens are added tokens only in `Match` mode. In `Plain` mode they are left out
+    // and encoded as text, as here
+    fn huggingface_added_tokens(&self) -> Vec<Value> {
+        let matching = self.special_token_mode == SpecialTokenMode::Match;
+        let fixed = self.config.control_tokens().into_iter().chain([&self.config.unknown])
+            .filter(|_| matching)
+            .map(|token| AddedToken { index: token.index, ..AddedToken::new(&token.value, true) });
+        let added = self.config.added_tokens.iter().filter(|token| matching || !token.special).cloned();
+        let mut tokens: Vec<AddedToken> = fixed.chain(added).collect();
+        tokens.sort_by_key(|token| token.index);
+        tokens.dedup_by_key(|token| token.index);
+        tokens.into_iter().map(|token| json!({
+            "id": token.index,
+            "content": token.value,
+            "single_word": token.single_word,
+            "lstrip": token.lstrip,
+            "rstrip": token.rstrip,
+            "normalized": token.normalized,
+            "special": token.special,
+        })).collect()
+    }
+
+    fn post_processor_json(&self, processor: &PostProcessor) -> Value {
+        let template = |template: &Template| -> Vec<Value> {
+            template.pieces.iter().map(|piece| match piece {
+                TemplatePiece::Sequence { second, type_id } => {
+                    json!({"Sequence": {"id": if *second { "B" } else { "A" }, "type_id": type_id}})
+                }
+                TemplatePiece::Special { token, type_id } => json!({"SpecialToken": {"id": token, "type_id": type_id}}),
+            }).collect()
+        };
+        let special_tokens: serde_json::Map<String, Value> = processor.single.special_tokens().chain(processor.pair.special_tokens())
+            .map(|token| (token.to_string(), json!({"id": token, "ids": [self.get_index(token)], "tokens": [token]})))
+            .collect();
+        json!({
+            "type": "TemplateProcessing",
+            "single": template(&processor.single),
+            "pair": template(&processor.pair),
+            "special_tokens": special_tokens,
+        })
+    }
+}
+
+// Templates are read from `TemplateProcessing`, alone or in a sequence - other post-processors only
+// change offsets, or have no equivalent and leave encodings unwrapped
+fn post_processor(value: &Value) -> Result<Option<PostProcessor>> {
+    match component_type(value) {
+        "TemplateProcessing" => Ok(Some(PostProcessor {
+            single: template(array(value, "single"))?,
+            pair: template(array(value, "pair"))?,
+        })),
+        "Sequence" => {
+            for processor in array(value, "processors") {
+                if let Some(processor) = post_processor(processor)? {
+                    return Ok(Some(processor));
+                }
+            }
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+fn template(pieces: &[Value]) -> Result<Template> {
+    let pieces = pieces.iter().map(|piece| {
+        let type_id = |piece: &Value| piece.get("type_id").and_then(Value::as_u64).unwrap_or(0) as u8;
+        if let Some(sequence) = piece.get("Sequence") {
+            return Ok(TemplatePiece::Sequence { second: string(sequence, "id") == "B", type_id: type_id(sequence) });
+        }
+        match piece.get("SpecialToken") {
+            Some(special) => Ok(TemplatePiece::Special { token: string(special, "id").to_string(), type_id: type_id(special) }),
+            None => Err(TokenizerError::InvalidConfig(format!("Malformed template piece: {}", piece))),
+        }
+    }).collect::<Result<Vec<_>>>()?;
+    Ok(Template { pieces })
+}
+
+// No steps is null and one step stands alone
+fn sequence(key: &str, mut steps: Vec<Value>) -> Value {
+    match steps.len() {
+        0 => Value::Null,
+        1 => steps.remove(0),
+        _ => json!({"type": "Sequence", key: steps}),
+    }
+}
+
+fn normalizer_json(step: &NormalizerStep) -> Vec<Value> {
+    let replace = |pattern: &str, content: &str| json!({"type": "Replace", "pattern": {"Regex": pattern}, "content": content});
+    match step {
+        NormalizerStep::Nfc => vec![json!({"type": "NFC"})],
+        NormalizerStep::Nfd => vec![json!({"type": "NFD"})],
+        NormalizerStep::Nfkc => vec![json!({"type": "NFKC"})],
+        NormalizerStep::Nfkd => vec![json!({"type": "NFKD"})],
+        NormalizerStep::Lowercase => vec![json!({"type": "Lowercase"})],
+        NormalizerStep::StripAccents => vec![json!({"type": "StripAccents"})],
+        NormalizerStep::StripControl => vec![replace(r"[^\P{Cc}\t\n\r]", "")],
+        // Runs with a newline first, then the rest
+        NormalizerStep::CollapseWhitespace => vec![replace(r"\s*\n\s*", "\n"), replace(r"[^\S\n]+", " ")],
+        NormalizerStep::Replace { pattern, content } => vec![replace(&pattern.source, content)],
+        NormalizerStep::Prepend { content } => vec![json!({"type": "Prepend", "prepend": content})],
+    }
+}
+
+fn pre_tokenizer_json(step: &PreTokenizerStep) -> Value {
+    let split = |pattern: &str| json!({"type": "Split", "pattern": {"Regex": pattern}, "behavior": "Isolated", "invert": false});
+    match step {
+        PreTokenizerStep::Regex { pattern } => split(&pattern.source),
+        // Whitespace stays attached to the following word
+        PreTokenizerStep::Whitespace => split(r"\s*\S+|\s+"),
+        PreTokenizerStep::Punctuation => json!({"type": "Punctuation", "behavior": "Isolated"}),
+        PreTokenizerStep::Digits { individual } => json!({"type": "Digits", "individual_digits": individual}),
+    }
+}
+
+fn decoder_json(steps: &[DecoderStep]) -> Vec<Value> {
+    let mut decoders = Vec::new();
+    let mut word_boundary = false;
+    for step in steps {
+        match step {
+            DecoderStep::ByteFallback => decoders.push(json!({"type": "ByteFallback"})),
+            DecoderStep::WordBoundary { marker } => {
+                decoders.push(json!({"type": "Replace", "pattern": {"String": marker}, "content": " "}));
+                word_boundary = true;
+            }
+            DecoderStep::WordPiece { prefix } => decoders.push(json!({"type": "WordPiece", "prefix": prefix, "cleanup": false})),
+            DecoderStep::EosAsSpace | DecoderStep::CleanUp => {}
+        }
+    }
+    // The space a word boundary leaves at the very start is dropped once the tokens are joined
+    if word_boundary {
+        decoders.push(json!({"type": "Fuse"}));
+        decoders.push(json!({"type": "Strip", "content": " ", "start": 1, "stop": 0}));
+    }
+    decoders
+}
+
+// Map the special tokens of an imported model to the fixed tokens of `TokenConfig`. A model with a
+// single boundary token, like GPT-2's `<|endoftext|>`, uses it to start and end text. Tokens the model
+// lacks get their own new ids after the imported ones, so the unknown, padding and mask tokens are
+// never one of the model's control tokens
+pub(crate) fn special_tokens(vocabulary: &mut Vec<String>, added_tokens: Vec<AddedToken>, unk_token: Option<String>) -> TokenConfig {
+    let known: HashSet<&str> = vocabulary.iter().map(String::as_str).collect();
+    let find = |candidates: &[&str]| candidates.iter().find(|token| known.contains(**token)).map(|token| token.to_string());
+    let unknown = unk_token.filter(|token| known.contains(token.as_str())).or_else(|| find(UNKNOWN));
+    let (sot, eot) = (find(SOT).or_else(|| find(EOT)), find(EOT).or_else(|| find(SOT)));
+    let (pad, mask) = (find(PAD), find(MASK));
+
+    let mut config = TokenConfig::new();
+    let unknown = unknown.unwrap_or_else(|| config.unknown.value.clone());
+    let text = |value: &str| find(&[value]).unwrap_or_else(|| unknown.clone());
+    config.space.value = text(" ");
+    config.newline.value = text("\n");
+    config.carriage.value = text("\r");
+    config.sot.value = sot.unwrap_or(config.sot.value);
+    config.eot.value = eot.unwrap_or(config.eot.value);
+    config.eos.value = find(EOS).unwrap_or_else(|| config.eot.value.clone());
+    config.pad.value = pad.unwrap_or(config.pad.value);
+    config.mask.value = mask.unwrap_or(config.mask.value);
+    config.unknown.value = unknown;
+    for token in [&config.sot, &config.eot, &config.pad, &config.mask, &config.unknown] {
+        if !vocabulary.contains(&token.value) {
+            vocabulary.push(token.value.clone());
+        }
+    }
+
+    config.added_tokens = added_tokens;
+    config
+}
+
+#[derive(Default)]
+struct Components {
+    normalizer: Vec<NormalizerStep>,
+    pre_tokenizer: Vec<PreTokenizerStep>,
+    decoder: Vec<DecoderStep>,
+    // Tokens are spelled with GPT-2's printable stand-ins for bytes
+    byte_level: bool,
+}
+
+impl Components {
+    fn normalizer(&mut self, value: &Value) -> Result<()> {
+        let step = match component_type(value) {
+            "Sequence" => {
+                for normalizer in array(value, "normalizers") {
+                    self.normalizer(normalizer)?;
+                }
+                return Ok(());
+            }
+            "NFC" => NormalizerStep::Nfc,
+            "NFD" => NormalizerStep::Nfd,
+            "NFKC" => NormalizerStep::Nfkc,
+            "NFKD" => NormalizerStep::Nfkd,
+            "Lowercase" => NormalizerStep::Lowercase,
+            "StripAccents" => NormalizerStep::StripAccents,
+            "Replace" => NormalizerStep::Replace { pattern: pattern(value)?, content: string(value, "content").to_string() },
+            "Prepend" => NormalizerStep::Prepend { content: string(value, "prepend").to_string() },
+            "Strip" => {
+                for (side, pattern) in [("strip_left", r"^\s+"), ("strip_right", r"\s+$")] {
+                    if value.get(side).and_then(Value::as_bool).unwrap_or(true) {
+                        self.normalizer.push(NormalizerStep::Replace { pattern: Pattern::new(pattern)?, content: String::new() });
+                    }
+                }
+                return Ok(());
+            }
+            other => return Err(unsupported("normalizer", other)),
+        };
+        self.normalizer.push(step);
+        Ok(())
+    }
+
+    fn pre_tokenizer(&mut self, value: &Value) -> Result<()> {
+        match component_type(value) {
+            "Sequence" => {
+                for pre_tokenizer in array(value, "pretokenizers") {
+                    self.pre_tokenizer(pre_tokenizer)?;
+                }
+            }
+            "ByteLevel" => {
+                self.byte_level = true;
+                if value.get("add_prefix_space").and_then(Value::as_bool).unwrap_or(false) {
+                    self.normalizer.push(NormalizerStep::Prepend { content: " ".to_string() });
+                }
+                if value.get("use_regex").and_then(Value::as_bool).unwrap_or(true) {
+                    self.pre_tokenizer.push(PreTokenizerStep::regex(GPT2_PATTERN)?);
+                }
+            }
+            "Split" => {
+                let behavior = value.get("behavior").and_then(Value::as_str).unwrap_or("");
+                let invert = value.get("invert").and_then(Value::as_bool).unwrap_or(false);
+                if behavior != "Isolated" || invert {
+                    return Err(unsupported("pre-tokenizer", &format!("Split with behavior {}", behavior)));
+                }
+                self.pre_tokenizer.push(PreTokenizerStep::Regex { pattern: pattern(value)? });
+            }
+            "Punctuation" => self.pre_tokenizer.push(PreTokenizerStep::Punctuation),
+            "Digits" => {
+                let individual = value.get("individual_digits").and_then(Value::as_bool).unwrap_or(false);
+                self.pre_tokenizer.push(PreTokenizerStep::Digits { individual });
+            }
+            // Spaces become the replacement, which starts every piece
+            "Metaspace" => {
+                let replacement = string(value, "replacement");
+                let prepend = match value.get("prepend_scheme").and_then(Value::as_str) {
+                    Some(scheme) => scheme != "never",
+                    None => value.get("add_prefix_space").and_then(Value::as_bool).unwrap_or(true),
+                };
+                let escaped = fancy_regex::escape(replacement);
+                self.normalizer.push(NormalizerStep::Replace { pattern: Pattern::new(" ")?, content: replacement.to_string() });
+                if prepend {
+                    self.normalizer.push(NormalizerStep::Prepend { content: replacement.to_string() });
+                }
+                if value.get("split").and_then(Value::as_bool).unwrap_or(true) {
+                    self.pre_tokenizer.push(PreTokenizerStep::regex(&format!("{0}[^{0}]*", escaped))?);
+                }
+            }
+            other => return Err(unsupported("pre-tokenizer", other)),
+        }
+        Ok(())
+    }
+
+    fn decoder(&mut self, value: &Value) -> Result<()> {
+        match component_type(value) {
+            "Sequence" => {
+                for decoder in array(value, "decoders") {
+                    self.decoder(decoder)?;
+                }
+            }
+            // The vocabulary is already decoded to text
+            "ByteLevel" => self.byte_level = true,
+            "ByteFallback" => self.decoder.push(DecoderStep::ByteFallback),
+            "Metaspace" => self.decoder.push(DecoderStep::WordBoundary { marker: string(value, "replacement").to_string() }),
+            "Replace" if string(value, "content") == " " => {
+                let marker = value.get("pattern").and_then(|pattern| pattern.get("String")).and_then(Value::as_str);
+                let Some(marker) = marker else { return Err(unsupported("decoder", "Replace with a regex")) };
+                self.decoder.push(DecoderStep::WordBoundary { marker: marker.to_string() });
+            }
+            "WordPiece" => self.decoder.push(DecoderStep::WordPiece { prefix: string(value, "prefix").to_string() }),
+            // Pieces are always joined, and the word boundary step drops the leading space
+            "Fuse" | "Strip" => {}
+            other => return Err(unsupported("decoder", other)),
+        }
+        Ok(())
+    }
+}
+
+fn component_type(value: &Value) -> &str {
+    value.get("type").and_then(Value::as_str).unwrap_or("")
+}
+
+fn array<'a>(value: &'a Value, key: &str) -> &'a [Value] {
+    value.get(key).and_then(Value::as_array).map_or(&[], Vec::as_slice)
+}
+
+fn string<'a>(value: &'a Value, key: &str) -> &'a str {
+    value.get(key).and_then(Value::as_str).unwrap_or("")
+}
+
+// Patterns are either {"String": literal} or {"Regex": pattern}
+fn pattern(value: &Value) -> Result<Pattern> {
+    let pattern = value.get("pattern");
+    if let Some(literal) = pattern.and_then(|pattern| pattern.get("String")).and_then(Value::as_str) {
+        return Pattern::new(&fancy_regex::escape(literal));
+    }
+    match pattern.and_then(|pattern| pattern.get("Regex")).and_then(Value::as_str) {
+        Some(regex) => Pattern::new(regex),
+        None => Err(TokenizerError::InvalidConfig(format!("Missing pattern in {}", value))),
+    }
+}
+
+fn unsupported(component: &str, name: &str) -> TokenizerError {
+    TokenizerError::InvalidConfig(format!("Unsupported HuggingFace {}: {}", component, name))
+}
+
+// GPT-2 spells every byte with a printable char - printable Latin-1 bytes stand for themselves and
+// the rest are shifted past U+0100 in order
+pub(crate) fn byte_level_chars() -> [char; 256] {
+    let mut chars = ['\0'; 256];
+    let mut shifted = 0;
+    for byte in 0..=u8::MAX {
+        chars[byte as usize] = if matches!(byte, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF) {
+            byte as char
+        } else {
+            shifted += 1;
+            char::from_u32(0xFF + shifted).unwrap()
+        };
+    }
+    chars
+}
+
+fn byte_level_bytes() -> HashMap<char, u8> {
+    byte_level_chars().into_iter().zip(0..=u8::MAX).collect()
+}
+
+// The text a byte-level token stands for. Tokens that are not valid UTF-8 on their own become runs
+// of `<0xNN>` byte tokens, and tokens outside the byte-level alphabet are kept as they are
+fn from_byte_level(token: &str, bytes: &HashMap<char, u8>) -> String {
+    let token_bytes: Option<Vec<u8>> = token.chars().map(|c| bytes.get(&c).copied()).collect();
+    token_bytes.map_or_else(|| token.to_string(), bytes_token)
+}
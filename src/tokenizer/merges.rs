@@ -0,0 +1,170 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use serde::{Serialize, Deserialize};
+
+use crate::tokenizer::TokenizerError;
+use crate::tokenizer::bytes::token_bytes;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodingMode {
+    // Longest-prefix matching through the vocabulary trie
+    #[default]
+    Greedy,
+    // Apply the learned merge rules in rank order, as during training
+    Merge,
+}
+
+impl std::fmt::Display for EncodingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EncodingMode::Greedy => write!(f, "greedy"),
+            EncodingMode::Merge => write!(f, "merge"),
+        }
+    }
+}
+
+impl std::str::FromStr for EncodingMode {
+    type Err = TokenizerError;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode.to_lowercase().as_str() {
+            "greedy" => Ok(EncodingMode::Greedy),
+            "merge" => Ok(EncodingMode::Merge),
+            _ => Err(TokenizerError::InvalidConfig(format!("Unknown encoding mode: {}", mode))),
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct MergeRanks {
+    // (left, right) -> (rank, merged index)
+    ranks: HashMap<(usize, usize), (usize, usize)>,
+    // Index of the token for each single byte, for byte-level rules
+    bytes: Vec<Option<usize>>,
+}
+
+#[derive(Clone, Copy)]
+struct Symbol {
+    index: usize,
+    len: usize,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+impl MergeRanks {
+    pub fn new(merge_rules: &[(String, String)], token_to_index: &HashMap<String, usize>) -> Self {
+        let mut ranks = HashMap::with_capacity(merge_rules.len());
+        for (rank, (first, second)) in merge_rules.iter().enumerate() {
+            let pair = (token_to_index.get(first), token_to_index.get(second));
+            let merged = token_to_index.get(&format!("{}{}", first, second));
+            if let ((Some(&a), Some(&b)), Some(&merged)) = (pair, merged) {
+                ranks.entry((a, b)).or_insert((rank, merged));
+            }
+        }
+        MergeRanks { ranks, bytes: Vec::new() }
+    }
+
+    // Rules of a byte-level vocabulary, where a rule makes the token spelling the bytes of both parts
+    // even when they split a character. Words are merged from the tokens of single bytes
+    pub fn from_bytes(merge_rules: &[(String, String)], vocabulary: &[String]) -> Self {
+        let mut indices: HashMap<Vec<u8>, usize> = HashMap::with_capacity(vocabulary.len());
+        for (i, token) in vocabulary.iter().enumerate() {
+            indices.entry(token_bytes(token)).or_insert(i);
+        }
+        let mut ranks = HashMap::with_capacity(merge_rules.len());
+        for (rank, (first, second)) in merge_rules.iter().enumerate() {
+            let (first, second) = (token_bytes(first), token_bytes(second));
+            let merged = [first.as_slice(), second.as_slice()].concat();
+            if let (Some(&a), Some(&b), Some(&merged)) = (indices.get(&first), indices.get(&second), indices.get(&merged)) {
+                ranks.entry((a, b)).or_insert((rank, merged));
+            }
+        }
+        let bytes = (0..=u8::MAX).map(|byte| indices.get(&vec![byte]).copied()).collect();
+        MergeRanks { ranks, bytes }
+    }
+
+    // The token of a single byte in a byte-level vocabulary
+    pub fn byte(&self, byte: u8) -> Option<usize> {
+        self.bytes.get(byte as usize).copied().flatten()
+    }
+
+    // Drop rules that would produce one of the given tokens
+    pub fn without_merged(mut self, indices: &[usize]) -> Self {
+        self.ranks.retain(|_, (_, merged)| !indices.contains(merged));
+        self
+    }
+
+    pub fn get(&self, first: usize, second: usize) -> Option<(usize, usize)> {
+        self.ranks.get(&(first, second)).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranks.is_empty()
+    }
+
+    // Merge (index, byte length) symbols of a single word, lowest rank first
+    pub fn apply(&self, symbols: &[(usize, usize)]) -> Vec<(usize, usize)> {
+        self.apply_with(symbols, || true)
+    }
+
+    // As `apply`, but each candidate merge is only made if `keep` returns true. A skipped merge is
+    // reconsidered after the next merge is made, so the word is done once every candidate is skipped
+    pub fn apply_with(&self, symbols: &[(usize, usize)], mut keep: impl FnMut() -> bool) -> Vec<(usize, usize)> {
+        let count = symbols.len();
+        let mut word: Vec<Symbol> = symbols.iter().enumerate().map(|(i, &(index, len))| Symbol {
+            index,
+            len,
+            prev: i.checked_sub(1),
+            next: if i + 1 < count { Some(i + 1) } else { None },
+        }).collect();
+
+        // Ties on rank are resolved left to right, matching the training loop
+        let mut queue = BinaryHeap::new();
+        for i in 1..count {
+            if let Some((rank, _)) = self.get(word[i - 1].index, word[i].index) {
+                queue.push(Reverse((rank, i - 1)));
+            }
+        }
+
+        let mut skipped = Vec::new();
+        while let Some(Reverse((rank, pos))) = queue.pop() {
+            let Some(next) = word[pos].next else { continue };
+            if word[pos].len == 0 {
+                continue;
+            }
+            // Skip entries made stale by an earlier merge
+            let Some((current_rank, merged)) = self.get(word[pos].index, word[next].index) else { continue };
+            if current_rank != rank {
+                continue;
+            }
+            if !keep() {
+                skipped.push(Reverse((rank, pos)));
+                continue;
+            }
+            queue.extend(skipped.drain(..));
+
+            word[pos].index = merged;
+            word[pos].len += word[next].len;
+            word[pos].next = word[next].next;
+            word[next].len = 0;
+            if let Some(after) = word[next].next {
+                word[after].prev = Some(pos);
+            }
+
+            if let Some(prev) = word[pos].prev {
+                if let Some((rank, _)) = self.get(word[prev].index, merged) {
+                    queue.push(Reverse((rank, prev)));
+                }
+            }
+            if let Some(after) = word[pos].next {
+                if let Some((rank, _)) = self.get(merged, word[after].index) {
+                    queue.push(Reverse((rank, pos)));
+                }
+            }
+        }
+
+        word.iter().filter(|symbol| symbol.len > 0).map(|symbol| (symbol.index, symbol.len)).collect()
+    }
+}
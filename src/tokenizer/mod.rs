@@ -1,6 +1,42 @@
+#[allow(clippy::module_inception)]
 pub mod tokenizer;
 pub mod token_config;
 pub mod trie;
+pub mod merges;
+pub mod bytes;
+pub mod train_options;
+pub mod normalizer;
+pub mod pre_tokenizer;
+pub mod encoding;
+pub mod truncation;
+pub mod post_processor;
+pub mod added_tokens;
+pub mod decode_stream;
+pub mod encode_stream;
+pub mod decoder;
+pub mod error;
+pub mod sampling;
+pub mod model;
+pub mod unigram;
+pub mod wordpiece;
+pub mod huggingface;
+pub mod tiktoken;
 pub use tokenizer::Tokenizer;
-pub use token_config::TokenConfig;
-pub use trie::TrieNode;
\ No newline at end of file
+pub use token_config::{SpecialTokenMode, TokenConfig};
+pub use trie::TrieNode;
+pub use merges::{EncodingMode, MergeRanks};
+pub use train_options::TrainOptions;
+pub use normalizer::{NormalizedString, Normalizer, NormalizerPipeline, NormalizerStep};
+pub use pre_tokenizer::{PreTokenizer, PreTokenizerPipeline, PreTokenizerStep};
+pub use encoding::{BatchEncoding, BatchOptions, Encoding, Padding, PaddingSide};
+pub use truncation::{Truncation, TruncationStrategy};
+pub use post_processor::{PostProcessor, Template, TemplatePiece};
+pub use added_tokens::AddedToken;
+pub use decode_stream::DecodeStream;
+pub use encode_stream::{EncodeStream, ReadChunks};
+pub use decoder::{Decoder, DecoderPipeline, DecoderStep};
+pub use error::{Result, TokenizerError};
+pub use sampling::{Dropout, Rng};
+pub use model::Model;
+pub use unigram::Unigram;
+pub use wordpiece::WordPiece;
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::tokenizer::{SpecialTokenMode, TokenConfig};
+use crate::tokenizer::{Decoder, DecoderPipeline};
+use crate::tokenizer::{Normalizer, NormalizerPipeline};
+use crate::tokenizer::added_tokens::{added_matchers, find_matches, split_special_tokens};
+use crate::tokenizer::error::{Result, TokenizerError};
+
+
+// A model over a plain vocabulary that only differs in how it splits normalized text into tokens -
+// BPE's `Tokenizer`, `Unigram` and `WordPiece`. Lookups, special and added tokens, decoding and IO
+// are shared
+pub trait Model: Serialize + DeserializeOwned {
+    // Ordered vocabulary - the index of each token is its position in this list
+    fn vocabulary(&self) -> &[String];
+    fn token_to_index(&self) -> &HashMap<String, usize>;
+    fn config(&self) -> &TokenConfig;
+    fn normalizer(&self) -> &NormalizerPipeline;
+    fn decoder(&self) -> &DecoderPipeline;
+    fn special_token_mode(&self) -> SpecialTokenMode;
+    fn set_special_token_mode(&mut self, mode: SpecialTokenMode);
+    // Rebuild the lookups that are not saved
+    fn build_index(&mut self) -> Result<()>;
+    // Append the ids of normalized text that holds no special or added tokens
+    fn encode_text(&self, text: &str, ids: &mut Vec<usize>);
+
+    fn get_vocabulary(&self) -> Vec<String> {
+        self.vocabulary().to_vec()
+    }
+
+    fn vocab_size(&self) -> usize {
+        self.vocabulary().len()
+    }
+
+    fn get_token(&self, index: usize) -> Option<String> {
+        self.vocabulary().get(index).cloned()
+    }
+
+    fn get_index(&self, token: &str) -> Option<usize> {
+        self.token_to_index().get(token).copied()
+    }
+
+    fn get_tokens(&self, indices: &[usize]) -> Result<Vec<String>> {
+        indices.iter().map(|&idx| self.get_token(idx).ok_or(TokenizerError::UnknownIndex(idx))).collect()
+    }
+
+    fn get_indices(&self, tokens: &[String]) -> Result<Vec<usize>> {
+        tokens.iter().map(|token| self.get_index(token).ok_or_else(|| TokenizerError::UnknownToken(token.clone()))).collect()
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        self.normalizer().normalize(text)
+    }
+
+    // Added tokens, and control tokens in `Match` mode, are matched whole in the raw input. Added
+    // tokens marked as normalized are matched in the normalized text
+    fn tokenize(&self, input_text: &str) -> Vec<usize> {
+        tokenize_from(self, input_text, false)
+    }
+
+    // Decode ids through the configured decoder - ids outside the vocabulary decode as the unknown token
+    fn detokenize(&self, indices: &[usize]) -> String {
+        let (config, decoder) = (self.config(), self.decoder());
+        let tokens: Vec<String> = indices.iter()
+            .filter(|&&index| !(decoder.skip_special_tokens && config.is_control_token(index)))
+            .map(|&index| self.get_token(index).unwrap_or_else(|| config.unknown.value.clone()))
+            .collect();
+        decoder.decode_chain(tokens, config).concat()
+    }
+
+    fn save(&self, path: &str) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        Ok(std::fs::write(path, json)?)
+    }
+
+    fn load(path: &str) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        Self::from_json(&data)
+    }
+
+    fn from_json(json: &str) -> Result<Self> {
+        let mut model: Self = serde_json::from_str(json)?;
+        model.build_index()?;
+        Ok(model)
+    }
+}
+
+// Tokenize, normalizing leading text as a continuation of earlier text when `continued` is set
+pub(crate) fn tokenize_from<M: Model>(model: &M, input_text: &str, continued: bool) -> Vec<usize> {
+    let config = model.config();
+    let patterns: Vec<String> = config.added_tokens.iter()
+        .map(|token| if token.normalized { model.normalize(&token.value) } else { String::new() })
+        .collect();
+    let matchers = added_matchers(&config.added_tokens, &patterns, true, model.special_token_mode());
+
+    let mut ids = Vec::new();
+    for (start, segment, special) in split_special_tokens(input_text, config, model.special_token_mode()) {
+        if let Some(index) = special {
+            ids.push(index);
+            continue;
+        }
+        let text = match continued && start == 0 {
+            true => model.normalizer().continued().normalize(segment),
+            false => model.normalize(segment),
+        };
+        let mut last = 0;
+        for (start, end, index) in find_matches(&text, &matchers) {
+            model.encode_text(&text[last..start], &mut ids);
+            ids.push(index);
+            last = end;
+        }
+        model.encode_text(&text[last..], &mut ids);
+    }
+    ids
+}
+
+// Map each token to its index - the first occurrence wins
+pub(crate) fn index_tokens(vocabulary: &[String]) -> HashMap<String, usize> {
+    let mut token_to_index = HashMap::with_capacity(vocabulary.len());
+    for (i, token) in vocabulary.iter().enumerate() {
+        token_to_index.entry(token.clone()).or_insert(i);
+    }
+    token_to_index
+}
@@ -0,0 +1,396 @@
+use fancy_regex::Regex;
+use serde::{Serialize, Deserialize};
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::{canonical_combining_class, compose, is_combining_mark};
+
+use crate::tokenizer::error::Result;
+
+
+// Provenance tracked for each char during normalization - `()` when alignment is not needed
+pub trait Span: Copy {
+    fn join(self, other: Self) -> Self;
+}
+
+impl Span for () {
+    fn join(self, _: Self) -> Self {}
+}
+
+// Byte range in the original text
+impl Span for (usize, usize) {
+    fn join(self, other: Self) -> Self {
+        (self.0.min(other.0), self.1.max(other.1))
+    }
+}
+
+// Object safe, so normalizers can be used as `dyn Normalizer`
+pub trait Normalizer {
+    // Transform text with the original byte range of each char, returning the new text and its ranges
+    fn transform(&self, text: &str, spans: Vec<(usize, usize)>) -> (String, Vec<(usize, usize)>);
+
+    fn normalize(&self, text: &str) -> String {
+        self.normalize_aligned(text).normalized
+    }
+
+    fn normalize_aligned(&self, text: &str) -> NormalizedString {
+        let spans = text.char_indices().map(|(i, c)| (i, i + c.len_utf8())).collect();
+        let (normalized, spans) = self.transform(text, spans);
+        NormalizedString::new(normalized, spans, text.len())
+    }
+}
+
+// Normalized text with the original byte range behind each normalized byte
+#[derive(Debug, Clone, PartialEq)]
+pub struct NormalizedString {
+    pub normalized: String,
+    alignments: Vec<(usize, usize)>,
+    original_len: usize,
+}
+
+impl NormalizedString {
+    fn new(normalized: String, spans: Vec<(usize, usize)>, original_len: usize) -> Self {
+        let mut alignments = Vec::with_capacity(normalized.len());
+        for (c, span) in normalized.chars().zip(spans) {
+            alignments.extend(std::iter::repeat_n(span, c.len_utf8()));
+        }
+        NormalizedString { normalized, alignments, original_len }
+    }
+
+    // Map a byte range of the normalized text onto the original text
+    pub fn original_range(&self, start: usize, end: usize) -> (usize, usize) {
+        if start >= end {
+            let position = match self.alignments.get(start) {
+                Some(&(original, _)) => original,
+                None => self.alignments.last().map_or(self.original_len, |&(_, end)| end),
+            };
+            return (position, position);
+        }
+        (self.alignments[start].0, self.alignments[end - 1].1)
+    }
+}
+
+// The steps a pipeline can hold. Pipelines are saved with the tokenizer, so new normalizations are
+// added here as variants rather than as outside `Normalizer` implementations
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum NormalizerStep {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+    Lowercase,
+    // Decompose and drop combining marks, e.g. "é" -> "e"
+    StripAccents,
+    // Drop control characters other than tabs and line breaks
+    StripControl,
+    // Replace each run of whitespace with one space, or one newline if the run contains one
+    CollapseWhitespace,
+    // Replace every match of a regex pattern with the given content. If the regex engine gives up at
+    // runtime - fancy_regex's backtrack limit - the text it did not search is left unchanged
+    Replace { pattern: Pattern, content: String },
+    // Put the given content before any non-empty text
+    Prepend { content: String },
+}
+
+impl NormalizerStep {
+    // Spans are `()` when alignment is not needed
+    fn apply<S: Span>(&self, text: &str, spans: Vec<S>) -> (String, Vec<S>) {
+        let mut output = Output::with_capacity(text.len());
+        let chars = text.chars().zip(spans);
+        match self {
+            NormalizerStep::Nfc => unicode_segments(chars, &mut output, |s| s.nfc().collect()),
+            NormalizerStep::Nfd => unicode_segments(chars, &mut output, |s| s.nfd().collect()),
+            NormalizerStep::Nfkc => unicode_segments(chars, &mut output, |s| s.nfkc().collect()),
+            NormalizerStep::Nfkd => unicode_segments(chars, &mut output, |s| s.nfkd().collect()),
+            NormalizerStep::Lowercase => lowercase(chars, &mut output),
+            NormalizerStep::StripAccents => {
+                for (c, span) in chars {
+                    for c in std::iter::once(c).nfd().filter(|&c| !is_combining_mark(c)) {
+                        output.push(c, span);
+                    }
+                }
+            }
+            NormalizerStep::StripControl => {
+                for (c, span) in chars {
+                    if !c.is_control() || matches!(c, '\t' | '\n' | '\r') {
+                        output.push(c, span);
+                    }
+                }
+            }
+            NormalizerStep::CollapseWhitespace => collapse_whitespace(chars, &mut output),
+            NormalizerStep::Replace { pattern, content } => {
+                return replace(pattern.regex(), content, text, chars.map(|(_, span)| span).collect());
+            }
+            NormalizerStep::Prepend { content } => {
+                // Prepended chars take the span of the first char
+                let mut chars = chars.peekable();
+                if let Some(&(_, span)) = chars.peek() {
+                    for c in content.chars() {
+                        output.push(c, span);
+                    }
+                }
+                for (c, span) in chars {
+                    output.push(c, span);
+                }
+            }
+        }
+        (output.text, output.spans)
+    }
+}
+
+impl Normalizer for NormalizerStep {
+    fn transform(&self, text: &str, spans: Vec<(usize, usize)>) -> (String, Vec<(usize, usize)>) {
+        self.apply(text, spans)
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        self.apply(text, vec![(); text.chars().count()]).0
+    }
+}
+
+struct Output<S> {
+    text: String,
+    spans: Vec<S>,
+}
+
+impl<S: Span> Output<S> {
+    fn with_capacity(capacity: usize) -> Self {
+        Output { text: String::with_capacity(capacity), spans: Vec::with_capacity(capacity) }
+    }
+
+    fn push(&mut self, c: char, span: S) {
+        self.text.push(c);
+        self.spans.push(span);
+    }
+}
+
+// Normalize runs that compose independently - every output char takes the span of its whole segment
+fn unicode_segments<S: Span>(
+    chars: impl Iterator<Item = (char, S)>,
+    output: &mut Output<S>,
+    normalize: impl Fn(&str) -> String,
+) {
+    let mut segment = String::new();
+    let mut segment_span: Option<S> = None;
+    let flush = |segment: &mut String, span: S, output: &mut Output<S>| {
+        // ASCII is unchanged by every normalization form
+        if segment.is_ascii() {
+            output.text.push_str(segment);
+            output.spans.extend(std::iter::repeat_n(span, segment.len()));
+        } else {
+            for c in normalize(segment).chars() {
+                output.push(c, span);
+            }
+        }
+        segment.clear();
+    };
+
+    for (c, span) in chars {
+        if let (Some(prev), Some(current)) = (segment.chars().next_back(), segment_span) {
+            if starts_segment(prev, c) {
+                flush(&mut segment, current, output);
+                segment_span = None;
+            }
+        }
+        segment.push(c);
+        segment_span = Some(segment_span.map_or(span, |current| current.join(span)));
+    }
+    if let Some(span) = segment_span {
+        flush(&mut segment, span, output);
+    }
+}
+
+fn starts_segment(prev: char, c: char) -> bool {
+    // Hangul vowel and trailing jamo compose with the preceding syllable
+    let hangul_jamo = ('\u{1160}'..='\u{11FF}').contains(&c);
+    canonical_combining_class(c) == 0 && compose(prev, c).is_none() && !hangul_jamo
+}
+
+fn lowercase<S: Span>(chars: impl Iterator<Item = (char, S)>, output: &mut Output<S>) {
+    let mut chars = chars.peekable();
+    let mut prev_alphabetic = false;
+    while let Some((c, span)) = chars.next() {
+        // Capital sigma becomes final sigma at the end of a word
+        let next_alphabetic = chars.peek().is_some_and(|(next, _)| next.is_alphabetic());
+        if c == 'Σ' && prev_alphabetic && !next_alphabetic {
+            output.push('ς', span);
+        } else {
+            for lower in c.to_lowercase() {
+                output.push(lower, span);
+            }
+        }
+        prev_alphabetic = c.is_alphabetic();
+    }
+}
+
+fn collapse_whitespace<S: Span>(chars: impl Iterator<Item = (char, S)>, output: &mut Output<S>) {
+    // The separator of each run takes the span of the whole run
+    let mut run: Option<(char, S)> = None;
+    for (c, span) in chars {
+        if c.is_whitespace() {
+            run = Some(match run {
+                Some((separator, current)) => (if c == '\n' { '\n' } else { separator }, current.join(span)),
+                None => (if c == '\n' { '\n' } else { ' ' }, span),
+            });
+        } else {
+            if let Some((separator, run_span)) = run.take() {
+                output.push(separator, run_span);
+            }
+            output.push(c, span);
+        }
+    }
+    if let Some((separator, run_span)) = run {
+        output.push(separator, run_span);
+    }
+}
+
+fn replace<S: Span>(regex: &Regex, content: &str, text: &str, spans: Vec<S>) -> (String, Vec<S>) {
+    let mut output = Output::with_capacity(text.len());
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut last = 0;
+    for found in regex.find_iter(text) {
+        // A runtime error ends the search, copying the rest through below
+        let Ok(found) = found else { break };
+        if found.start() == found.end() {
+            continue;
+        }
+        let first = chars.partition_point(|&(i, _)| i < found.start());
+        let end = chars.partition_point(|&(i, _)| i < found.end());
+
+        for i in last..first {
+            output.push(chars[i].1, spans[i]);
+        }
+        // Replacement chars take the span of the whole match
+        let span = spans[first + 1..end].iter().fold(spans[first], |span, &other| span.join(other));
+        for c in content.chars() {
+            output.push(c, span);
+        }
+        last = end;
+    }
+    for i in last..chars.len() {
+        output.push(chars[i].1, spans[i]);
+    }
+    (output.text, output.spans)
+}
+
+// Regex pattern that serializes as its source string
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    pub source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self> {
+        Ok(Pattern { source: source.to_string(), regex: Regex::new(source)? })
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    // The text the pattern matches when it has no regex syntax, as for escaped strings
+    pub fn literal(&self) -> Option<String> {
+        let mut literal = String::new();
+        let mut chars = self.source.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(c) if c.is_ascii_punctuation() => literal.push(c),
+                    _ => return None,
+                },
+                '.' | '^' | '$' | '*' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '|' => return None,
+                c => literal.push(c),
+            }
+        }
+        Some(literal)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        Pattern::new(&source).map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(transparent)]
+pub struct NormalizerPipeline {
+    pub steps: Vec<NormalizerStep>,
+}
+
+impl NormalizerPipeline {
+    pub fn new(steps: Vec<NormalizerStep>) -> Self {
+        NormalizerPipeline { steps }
+    }
+
+    // Original behaviour - lowercase only
+    pub fn uncased() -> Self {
+        Self::new(vec![NormalizerStep::Lowercase])
+    }
+
+    pub fn cased() -> Self {
+        Self::new(Vec::new())
+    }
+
+    pub fn is_cased(&self) -> bool {
+        !self.steps.contains(&NormalizerStep::Lowercase)
+    }
+
+    // Whether text cut after a line break, before a letter, digit or ASCII punctuation, normalizes
+    // the same in pieces as whole - with Prepend only applied to the first piece. Every step acts on
+    // single chars, whitespace runs or the start of the text, except replacements, which must swap
+    // literal text without line breaks for text without whitespace
+    pub fn cuts_at_line_breaks(&self) -> bool {
+        self.steps.iter().all(|step| match step {
+            NormalizerStep::Replace { pattern, content } => {
+                let literal = pattern.literal().unwrap_or_default();
+                !literal.is_empty() && !literal.contains('\n') && !content.is_empty() && !content.contains(char::is_whitespace)
+            }
+            _ => true,
+        })
+    }
+
+    // The pipeline for text that continues earlier text of the same input, without Prepend steps
+    pub fn continued(&self) -> Self {
+        Self::new(self.steps.iter().filter(|step| !matches!(step, NormalizerStep::Prepend { .. })).cloned().collect())
+    }
+}
+
+impl Default for NormalizerPipeline {
+    fn default() -> Self {
+        Self::uncased()
+    }
+}
+
+impl NormalizerPipeline {
+    fn apply<S: Span>(&self, text: &str, spans: Vec<S>) -> (String, Vec<S>) {
+        let mut steps = self.steps.iter();
+        let Some(first) = steps.next() else {
+            return (text.to_string(), spans);
+        };
+        steps.fold(first.apply(text, spans), |(text, spans), step| step.apply(&text, spans))
+    }
+}
+
+impl Normalizer for NormalizerPipeline {
+    fn transform(&self, text: &str, spans: Vec<(usize, usize)>) -> (String, Vec<(usize, usize)>) {
+        self.apply(text, spans)
+    }
+
+    fn normalize(&self, text: &str) -> String {
+        self.apply(text, vec![(); text.chars().count()]).0
+    }
+}
@@ -0,0 +1,134 @@
+use serde::{Serialize, Deserialize};
+
+use crate::tokenizer::Encoding;
+use crate::tokenizer::token_config::Token;
+use crate::tokenizer::error::{Result, TokenizerError};
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplatePiece {
+    // `$A` or `$B`, the encoded first or second input
+    Sequence { second: bool, type_id: u8 },
+    Special { token: String, type_id: u8 },
+}
+
+// Space separated pieces such as `<|sot|> $A <|eot|> $B:1 <|eot|>:1`, with an optional `:type_id` suffix
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub pieces: Vec<TemplatePiece>,
+}
+
+impl Template {
+    pub fn special_tokens(&self) -> impl Iterator<Item = &str> {
+        self.pieces.iter().filter_map(|piece| match piece {
+            TemplatePiece::Special { token, .. } => Some(token.as_str()),
+            TemplatePiece::Sequence { .. } => None,
+        })
+    }
+
+    fn parse_piece(piece: &str) -> Result<TemplatePiece> {
+        let (name, type_id) = match piece.rsplit_once(':') {
+            Some((name, type_id)) if !name.is_empty() && type_id.chars().all(|c| c.is_ascii_digit()) && !type_id.is_empty() => {
+                let type_id = type_id.parse()
+                    .map_err(|_| TokenizerError::InvalidConfig(format!("Invalid type id in template piece: {}", piece)))?;
+                (name, type_id)
+            }
+            _ => (piece, 0),
+        };
+        Ok(match name {
+            "$A" => TemplatePiece::Sequence { second: false, type_id },
+            "$B" => TemplatePiece::Sequence { second: true, type_id },
+            _ if name.starts_with('$') => {
+                return Err(TokenizerError::InvalidConfig(format!("Unknown template sequence: {}", name)));
+            }
+            _ => TemplatePiece::Special { token: name.to_string(), type_id },
+        })
+    }
+}
+
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pieces: Vec<String> = self.pieces.iter().map(|piece| {
+            let (name, type_id) = match piece {
+                TemplatePiece::Sequence { second, type_id } => (if *second { "$B" } else { "$A" }, *type_id),
+                TemplatePiece::Special { token, type_id } => (token.as_str(), *type_id),
+            };
+            if type_id == 0 { name.to_string() } else { format!("{}:{}", name, type_id) }
+        }).collect();
+        write!(f, "{}", pieces.join(" "))
+    }
+}
+
+impl std::str::FromStr for Template {
+    type Err = TokenizerError;
+
+    fn from_str(template: &str) -> Result<Self> {
+        let pieces = template.split_whitespace().map(Template::parse_piece).collect::<Result<_>>()?;
+        Ok(Template { pieces })
+    }
+}
+
+impl Serialize for Template {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let template = String::deserialize(deserializer)?;
+        template.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PostProcessor {
+    pub single: Template,
+    pub pair: Template,
+}
+
+impl PostProcessor {
+    pub fn new(single: &str, pair: &str) -> Result<Self> {
+        let processor = PostProcessor { single: single.parse()?, pair: pair.parse()? };
+        if processor.single.pieces.iter().any(|piece| matches!(piece, TemplatePiece::Sequence { second: true, .. })) {
+            return Err(TokenizerError::InvalidConfig("Single template cannot use $B".to_string()));
+        }
+        Ok(processor)
+    }
+
+    // `sot $A eot` and `sot $A eot $B:1 eot:1`
+    pub fn wrap(sot: &str, eot: &str) -> Self {
+        let piece = |token: &str, type_id| TemplatePiece::Special { token: token.to_string(), type_id };
+        let sequence = |second, type_id| TemplatePiece::Sequence { second, type_id };
+        PostProcessor {
+            single: Template { pieces: vec![piece(sot, 0), sequence(false, 0), piece(eot, 0)] },
+            pair: Template { pieces: vec![piece(sot, 0), sequence(false, 0), piece(eot, 0), sequence(true, 1), piece(eot, 1)] },
+        }
+    }
+
+    // Number of special tokens the template adds around the input
+    pub fn added_tokens(&self, pair: bool) -> usize {
+        let template = if pair { &self.pair } else { &self.single };
+        template.special_tokens().count()
+    }
+
+    // Lay out the encoded inputs and special tokens as the template describes, setting type ids
+    pub fn process(&self, first: Encoding, second: Option<Encoding>, resolve: impl Fn(&str) -> Token) -> Encoding {
+        let template = if second.is_some() { &self.pair } else { &self.single };
+        let mut parts = Vec::with_capacity(template.pieces.len());
+        for piece in template.pieces.iter() {
+            let (mut part, type_id) = match piece {
+                TemplatePiece::Sequence { second: false, type_id } => (first.clone(), *type_id),
+                TemplatePiece::Sequence { second: true, type_id } => match &second {
+                    Some(second) => (second.clone(), *type_id),
+                    None => continue,
+                },
+                TemplatePiece::Special { token, type_id } => (Encoding::special(&resolve(token)), *type_id),
+            };
+            part.type_ids = vec![type_id; part.len()];
+            parts.push(part);
+        }
+        Encoding::concat(&parts)
+    }
+}
+
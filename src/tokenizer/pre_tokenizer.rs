@@ -0,0 +1,137 @@
+use serde::{Serialize, Deserialize};
+
+use crate::tokenizer::normalizer::Pattern;
+use crate::tokenizer::error::Result;
+
+
+pub const GPT2_PATTERN: &str = r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+(?!\S)|\s+";
+pub const CL100K_PATTERN: &str = r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+";
+
+pub trait PreTokenizer {
+    // Split text into (byte offset, piece) pairs that together cover the whole input
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Vec<(usize, &'a str)>;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum PreTokenizerStep {
+    // Each regex match becomes a piece, as does any text between matches. If the regex engine gives up
+    // at runtime - fancy_regex's backtrack limit - the text it did not search stays one piece
+    Regex { pattern: Pattern },
+    // Split before each run of whitespace, keeping it attached to the following word
+    Whitespace,
+    // Isolate every punctuation character
+    Punctuation,
+    // Isolate runs of digits, or every digit when `individual` is set
+    Digits { individual: bool },
+}
+
+impl PreTokenizerStep {
+    pub fn regex(pattern: &str) -> Result<Self> {
+        Ok(PreTokenizerStep::Regex { pattern: Pattern::new(pattern)? })
+    }
+
+    // Byte ranges of the pieces of `text`
+    fn split(&self, text: &str) -> Vec<(usize, usize)> {
+        match self {
+            PreTokenizerStep::Regex { pattern } => {
+                let mut ranges = Vec::new();
+                let mut last = 0;
+                for found in pattern.regex().find_iter(text) {
+                    // A runtime error ends the search, leaving the rest for the piece below
+                    let Ok(found) = found else { break };
+                    if found.start() > last {
+                        ranges.push((last, found.start()));
+                    }
+                    if found.end() > found.start() {
+                        ranges.push((found.start(), found.end()));
+                    }
+                    last = found.end();
+                }
+                if last < text.len() {
+                    ranges.push((last, text.len()));
+                }
+                ranges
+            }
+            PreTokenizerStep::Whitespace => split_before(text, |prev, c| c.is_whitespace() && !prev.is_whitespace()),
+            PreTokenizerStep::Punctuation => split_before(text, |prev, c| is_punctuation(prev) || is_punctuation(c)),
+            PreTokenizerStep::Digits { individual } => split_before(text, |prev, c| {
+                if *individual {
+                    prev.is_numeric() || c.is_numeric()
+                } else {
+                    prev.is_numeric() != c.is_numeric()
+                }
+            }),
+        }
+    }
+}
+
+fn split_before(text: &str, is_boundary: impl Fn(char, char) -> bool) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    let mut prev = None;
+    for (i, c) in text.char_indices() {
+        if let Some(prev) = prev {
+            if is_boundary(prev, c) {
+                ranges.push((start, i));
+                start = i;
+            }
+        }
+        prev = Some(c);
+    }
+    if start < text.len() {
+        ranges.push((start, text.len()));
+    }
+    ranges
+}
+
+fn is_punctuation(c: char) -> bool {
+    // ASCII punctuation plus the General and CJK punctuation blocks
+    c.is_ascii_punctuation()
+        || ('\u{2010}'..='\u{2027}').contains(&c)
+        || ('\u{2030}'..='\u{205E}').contains(&c)
+        || ('\u{3001}'..='\u{3003}').contains(&c)
+        || ('\u{3008}'..='\u{3011}').contains(&c)
+}
+
+impl PreTokenizer for PreTokenizerStep {
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Vec<(usize, &'a str)> {
+        self.split(text).into_iter().map(|(start, end)| (start, &text[start..end])).collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(transparent)]
+pub struct PreTokenizerPipeline {
+    pub steps: Vec<PreTokenizerStep>,
+}
+
+impl PreTokenizerPipeline {
+    pub fn new(steps: Vec<PreTokenizerStep>) -> Self {
+        PreTokenizerPipeline { steps }
+    }
+
+    pub fn gpt2() -> Self {
+        Self::new(vec![PreTokenizerStep::regex(GPT2_PATTERN).unwrap()])
+    }
+
+    pub fn cl100k() -> Self {
+        Self::new(vec![PreTokenizerStep::regex(CL100K_PATTERN).unwrap()])
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+}
+
+impl PreTokenizer for PreTokenizerPipeline {
+    fn pre_tokenize<'a>(&self, text: &'a str) -> Vec<(usize, &'a str)> {
+        let mut pieces = if text.is_empty() { Vec::new() } else { vec![(0, text)] };
+        for step in self.steps.iter() {
+            pieces = pieces.into_iter().flat_map(|(offset, piece)| {
+                step.split(piece).into_iter().map(move |(start, end)| (offset + start, &piece[start..end]))
+            }).collect();
+        }
+        pieces
+    }
+}
@@ -0,0 +1,50 @@
+use std::cell::RefCell;
+
+use crate::tokenizer::error::{Result, TokenizerError};
+
+
+// SplitMix64 - small and identical on every platform, so a seed always gives the same samples
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1)
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+// BPE-dropout: every candidate merge is skipped with probability `p`
+#[derive(Debug)]
+pub struct Dropout {
+    pub p: f64,
+    rng: RefCell<Rng>,
+}
+
+impl Dropout {
+    pub fn new(p: f64, seed: u64) -> Result<Self> {
+        if !(0.0..=1.0).contains(&p) {
+            return Err(TokenizerError::InvalidConfig(format!("Dropout must be between 0 and 1, got {}", p)));
+        }
+        Ok(Dropout { p, rng: RefCell::new(Rng::new(seed)) })
+    }
+
+    // Whether the next candidate merge is made
+    pub fn keep(&self) -> bool {
+        self.p == 0.0 || self.rng.borrow_mut().next_f64() >= self.p
+    }
+}
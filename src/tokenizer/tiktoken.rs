@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+
+use crate::tokenizer::{Tokenizer, EncodingMode};
+use crate::tokenizer::{NormalizerPipeline, PreTokenizerPipeline, PreTokenizerStep};
+use crate::tokenizer::{DecoderPipeline, DecoderStep};
+use crate::tokenizer::added_tokens::AddedToken;
+use crate::tokenizer::huggingface;
+use crate::tokenizer::bytes::bytes_token;
+use crate::tokenizer::error::{Result, TokenizerError};
+
+
+impl Tokenizer {
+    pub fn load_tiktoken(path: &str, pattern: &str, special_tokens: &HashMap<String, usize>) -> Result<Self> {
+        let data = std::fs::read_to_string(path)?;
+        Self::from_tiktoken(&data, pattern, special_tokens)
+    }
+
+    // Build a byte-level tokenizer from the lines of a `.tiktoken` file - base64 token bytes and a
+    // rank each - with the split regex and special tokens of its encoding. Ids equal the ranks, and
+    // ids no token uses are kept as empty tokens that never match
+    pub fn from_tiktoken(data: &str, pattern: &str, special_tokens: &HashMap<String, usize>) -> Result<Self> {
+        let mut ranks = HashMap::new();
+        for (number, line) in data.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            let invalid = || TokenizerError::InvalidConfig(format!("Invalid tiktoken line {}: {}", number + 1, line));
+            let (token, rank) = line.trim().split_once(' ').ok_or_else(invalid)?;
+            let rank: usize = rank.parse().map_err(|_| invalid())?;
+            ranks.insert(decode_base64(token).ok_or_else(invalid)?, rank);
+        }
+
+        let size = ranks.values().chain(special_tokens.values()).max().map_or(0, |&id| id + 1);
+        let mut vocabulary = vec![String::new(); size];
+        for (bytes, &rank) in ranks.iter() {
+            vocabulary[rank] = bytes_token(bytes.clone());
+        }
+        let mut added_tokens: Vec<AddedToken> = special_tokens.iter()
+            .map(|(value, &index)| AddedToken { index, ..AddedToken::new(value, true) })
+            .collect();
+        added_tokens.sort_by_key(|token| token.index);
+        for token in added_tokens.iter() {
+            if !vocabulary[token.index].is_empty() {
+                return Err(TokenizerError::InvalidConfig(format!("Special token {} has the rank of a token", token.value)));
+            }
+            vocabulary[token.index] = token.value.clone();
+        }
+
+        // tiktoken merges the pair whose concatenation has the lowest rank, so every split of a
+        // token into two tokens is a merge rule ranked by the token, splits inside a character too
+        let mut merges = Vec::new();
+        for (bytes, &rank) in ranks.iter() {
+            for split in 1..bytes.len() {
+                if let (Some(&first), Some(&second)) = (ranks.get(&bytes[..split]), ranks.get(&bytes[split..])) {
+                    merges.push((rank, first, second));
+                }
+            }
+        }
+        merges.sort_unstable();
+        let merge_rules = merges.into_iter()
+            .map(|(_, first, second)| (vocabulary[first].clone(), vocabulary[second].clone()))
+            .collect();
+
+        let config = huggingface::special_tokens(&mut vocabulary, added_tokens, None);
+        let mut tokenizer = Tokenizer::new(vocabulary, merge_rules, config)?;
+        tokenizer.mode = EncodingMode::Merge;
+        tokenizer.byte_fallback = true;
+        tokenizer.enable_byte_level()?;
+        tokenizer.normalizer = NormalizerPipeline::cased();
+        tokenizer.pre_tokenizer = PreTokenizerPipeline::new(vec![PreTokenizerStep::regex(pattern)?]);
+        tokenizer.decoder = DecoderPipeline::new(vec![DecoderStep::ByteFallback]);
+        Ok(tokenizer)
+    }
+}
+
+// Standard base64 with optional padding, as tiktoken writes it
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
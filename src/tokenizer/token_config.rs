@@ -1,5 +1,12 @@
+use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
 
+use crate::tokenizer::added_tokens::AddedToken;
+use crate::tokenizer::error::{Result, TokenizerError};
+
+// Number of fixed special tokens, from sot to carriage
+pub const FIXED_TOKENS: usize = 9;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Token {
     pub value: String,
@@ -17,6 +24,15 @@ pub struct TokenConfig {
     pub space: Token,
     pub newline: Token,
     pub carriage: Token,
+    // User-defined tokens, after the fixed ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub added_tokens: Vec<AddedToken>,
+}
+
+impl Default for TokenConfig {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TokenConfig {
@@ -31,47 +47,75 @@ impl TokenConfig {
             space: Token {value: " ".to_string(), index: 6},
             newline: Token {value: "\n".to_string(), index: 7},
             carriage: Token {value: "\r".to_string(), index: 8},
+            added_tokens: Vec::new(),
         }
     }
 
-    pub fn get_values(&self) -> Vec<String> {
-        vec![
-            self.sot.value.clone(),
-            self.eot.value.clone(),
-            self.eos.value.clone(),
-            self.pad.value.clone(),
-            self.mask.value.clone(),
-            self.unknown.value.clone(),
-            self.space.value.clone(),
-            self.newline.value.clone(),
-            self.carriage.value.clone(),
+    // The fixed special tokens, in the order of `get_values` and `set_indices`
+    pub fn fixed_tokens(&self) -> [&Token; FIXED_TOKENS] {
+        [
+            &self.sot,
+            &self.eot,
+            &self.eos,
+            &self.pad,
+            &self.mask,
+            &self.unknown,
+            &self.space,
+            &self.newline,
+            &self.carriage,
         ]
     }
 
+    pub fn get_values(&self) -> Vec<String> {
+        let fixed = self.fixed_tokens().map(|token| token.value.clone());
+        fixed.into_iter().chain(self.added_tokens.iter().map(|token| token.value.clone())).collect()
+    }
+
     pub fn get_indices(&self) -> Vec<usize> {
-        vec![
-            self.sot.index,
-            self.eot.index,
-            self.eos.index,
-            self.pad.index,
-            self.mask.index,
-            self.unknown.index,
-            self.space.index,
-            self.newline.index,
-            self.carriage.index,
-        ]
+        let fixed = self.fixed_tokens().map(|token| token.index);
+        fixed.into_iter().chain(self.added_tokens.iter().map(|token| token.index)).collect()
     }
 
     pub fn is_eos(&self, token: &str) -> bool {
         token == self.eos.value
     }
 
+    // Control tokens mark structure rather than text - unlike space, newline, carriage and unknown
+    pub fn control_tokens(&self) -> [&Token; 5] {
+        [&self.sot, &self.eot, &self.eos, &self.pad, &self.mask]
+    }
+
+    // Fixed control tokens and special added tokens
+    pub fn is_control_token(&self, index: usize) -> bool {
+        self.control_tokens().iter().any(|token| token.index == index)
+            || self.added_tokens.iter().any(|token| token.special && token.index == index)
+    }
+
+    pub fn is_added_token(&self, index: usize) -> bool {
+        self.added_tokens.iter().any(|token| token.index == index)
+    }
+
     pub fn is_special_token(&self, token: &str) -> bool {
-        self.get_values().contains(&token.to_string())
+        self.fixed_tokens().iter().any(|fixed| fixed.value == token)
+            || self.added_tokens.iter().any(|added| added.special && added.value == token)
     }
 
-    pub fn set_indices(&mut self, indices: Vec<usize>) {
-        assert_eq!(indices.len(), 9, "Indices vector must have 9 elements.");
+    // Look up the index of every token in a vocabulary
+    pub fn resolve_indices(&mut self, token_to_index: &HashMap<String, usize>) -> Result<()> {
+        let mut indices = Vec::new();
+        for token in self.get_values() {
+            let index = *token_to_index.get(&token).ok_or(TokenizerError::MissingSpecialToken(token))?;
+            indices.push(index);
+        }
+        self.set_indices(indices)
+    }
+
+    // Indices of the fixed tokens followed by one per added token
+    pub fn set_indices(&mut self, indices: Vec<usize>) -> Result<()> {
+        let expected = FIXED_TOKENS + self.added_tokens.len();
+        if indices.len() != expected {
+            return Err(TokenizerError::InvalidConfig(format!("Indices vector must have {} elements.", expected)));
+        }
         self.sot.index = indices[0];
         self.eot.index = indices[1];
         self.eos.index = indices[2];
@@ -81,5 +125,41 @@ impl TokenConfig {
         self.space.index = indices[6];
         self.newline.index = indices[7];
         self.carriage.index = indices[8];
+        for (token, &index) in self.added_tokens.iter_mut().zip(indices[FIXED_TOKENS..].iter()) {
+            token.index = index;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SpecialTokenMode {
+    // Control tokens and the unknown token written in the input are matched whole, before
+    // normalization - only for trusted input
+    Match,
+    // Control tokens and the unknown token written in the input are encoded as plain text
+    #[default]
+    Plain,
+}
+
+impl std::fmt::Display for SpecialTokenMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpecialTokenMode::Match => write!(f, "match"),
+            SpecialTokenMode::Plain => write!(f, "plain"),
+        }
+    }
+}
+
+impl std::str::FromStr for SpecialTokenMode {
+    type Err = TokenizerError;
+
+    fn from_str(mode: &str) -> std::result::Result<Self, Self::Err> {
+        match mode.to_lowercase().as_str() {
+            "match" => Ok(SpecialTokenMode::Match),
+            "plain" => Ok(SpecialTokenMode::Plain),
+            _ => Err(TokenizerError::InvalidConfig(format!("Unknown special token mode: {}", mode))),
+        }
     }
 }
@@ -1,152 +1,705 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use dashmap::DashMap;
 use rayon::prelude::*;
 use std::io::{self, Write};
 use std::time::Instant;
 use serde::{Serialize, Deserialize};
-use serde_json;
 
 use crate::tokenizer::TokenConfig;
 use crate::tokenizer::TrieNode;
+use crate::tokenizer::{EncodingMode, MergeRanks, TrainOptions};
+use crate::tokenizer::{Normalizer, NormalizerPipeline, NormalizerStep};
+use crate::tokenizer::{PreTokenizer, PreTokenizerPipeline};
+use crate::tokenizer::{BatchEncoding, BatchOptions, Encoding};
+use crate::tokenizer::{Truncation, TruncationStrategy};
+use crate::tokenizer::PostProcessor;
+use crate::tokenizer::token_config::{SpecialTokenMode, Token};
+use crate::tokenizer::added_tokens::{added_matchers, find_matches, split_special_tokens, AddedToken};
+use crate::tokenizer::{EncodeStream, ReadChunks};
+use crate::tokenizer::DecoderPipeline;
+use crate::tokenizer::model::{index_tokens, Model};
+use crate::tokenizer::Dropout;
+use crate::tokenizer::error::{Result, TokenizerError};
+use crate::tokenizer::encoding::char_offsets;
+use crate::tokenizer::bytes::{byte_token, byte_tokens, parse_byte_tokens};
+
+// Separates pre-tokenized pieces in the training sequence
+const PIECE_BOUNDARY: usize = usize::MAX;
 
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Tokenizer {
     #[serde(skip_serializing, skip_deserializing)]
     pub vocabulary_trie: TrieNode,
-    pub vocabulary: HashSet<String>,
+    // Ordered vocabulary - the index of each token is its position in this list
+    pub vocabulary: Vec<String>,
     pub merge_rules: Vec<(String, String)>,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub merge_ranks: MergeRanks,
+    #[serde(default)]
+    pub mode: EncodingMode,
+    // Encode characters missing from the vocabulary as `<0xNN>` byte tokens instead of `<unk>`
+    #[serde(default)]
+    pub byte_fallback: bool,
+    // Tokens stand for bytes, and merging starts from single bytes so rules may split characters
+    #[serde(default)]
+    pub byte_level: bool,
+    // Applied to input text before encoding and to the corpus before training
+    #[serde(default)]
+    pub normalizer: NormalizerPipeline,
+    // `cased` flag of files saved before the normalizer pipeline, read once by `build_index`
+    #[serde(rename = "cased", default, skip_serializing)]
+    legacy_cased: Option<bool>,
+    // Splits normalized text into pieces that tokens never cross
+    #[serde(default)]
+    pub pre_tokenizer: PreTokenizerPipeline,
+    // Turns ids back into text
+    #[serde(default)]
+    pub decoder: DecoderPipeline,
+    // Whether control tokens written in the input are matched or encoded as text
+    #[serde(default)]
+    pub special_token_mode: SpecialTokenMode,
+    // Special token template applied by `encode` - `None` leaves encodings unwrapped
+    #[serde(default)]
+    pub post_processor: Option<PostProcessor>,
+    #[serde(skip_serializing, skip_deserializing)]
     pub token_to_index: HashMap<String, usize>,
-    pub index_to_token: HashMap<usize, String>,
     pub config: TokenConfig,
 }
 
 impl Tokenizer {
-    pub fn new(vocabulary: HashSet<String>, merge_rules: Vec<(String, String)>, config: TokenConfig) -> Self {
-        let mut vocabulary_trie = TrieNode::new();
-        let mut token_to_index = HashMap::new();
-        let mut index_to_token = HashMap::new();
-        
-        for (i, token) in vocabulary.iter().enumerate() {
-            token_to_index.insert(token.clone(), i);
-            index_to_token.insert(i, token.clone());
-            vocabulary_trie.insert(&token);
-        }
-
-        Tokenizer {
-            vocabulary_trie,
+    // Fails if a special token of `config` is missing from the vocabulary
+    pub fn new(vocabulary: Vec<String>, merge_rules: Vec<(String, String)>, config: TokenConfig) -> Result<Self> {
+        let mut tokenizer = Tokenizer {
+            vocabulary_trie: TrieNode::new(),
             vocabulary,
             merge_rules,
-            token_to_index,
-            index_to_token,
+            merge_ranks: MergeRanks::default(),
+            mode: EncodingMode::default(),
+            byte_fallback: false,
+            byte_level: false,
+            normalizer: NormalizerPipeline::default(),
+            legacy_cased: None,
+            pre_tokenizer: PreTokenizerPipeline::default(),
+            decoder: DecoderPipeline::default(),
+            special_token_mode: SpecialTokenMode::default(),
+            post_processor: None,
+            token_to_index: HashMap::new(),
             config,
+        };
+        tokenizer.build_index()?;
+        Ok(tokenizer)
+    }
+
+    pub fn get_merge_rules(&self) -> Vec<(String, String)> {
+        self.merge_rules.clone()
+    }
+
+    pub fn set_mode(&mut self, mode: EncodingMode) {
+        self.mode = mode;
+    }
+
+    pub fn enable_byte_fallback(&mut self) -> Result<()> {
+        // Append any missing byte tokens so existing indices stay unchanged
+        for token in byte_tokens() {
+            if !self.token_to_index.contains_key(&token) {
+                self.vocabulary.push(token);
+            }
         }
+        self.byte_fallback = true;
+        self.build_index()
     }
 
-    pub fn get_vocabulary(&self) -> HashSet<String> {
-        self.vocabulary.clone()
+    // Merge byte-level vocabularies, such as GPT-2's and tiktoken's, from single bytes
+    pub fn enable_byte_level(&mut self) -> Result<()> {
+        self.byte_level = true;
+        self.build_index()
     }
 
-    pub fn vocab_size(&self) -> usize {
-        self.vocabulary.len()
+    pub fn set_normalizer(&mut self, normalizer: NormalizerPipeline) {
+        self.normalizer = normalizer;
     }
 
-    pub fn get_merge_rules(&self) -> Vec<(String, String)> {
-        self.merge_rules.clone()
+    pub fn set_pre_tokenizer(&mut self, pre_tokenizer: PreTokenizerPipeline) {
+        self.pre_tokenizer = pre_tokenizer;
     }
 
-    pub fn get_token(&self, index: usize) -> Option<String> {
-        self.index_to_token.get(&index).cloned()
+    // Fails if the templates use a token that is not in the vocabulary
+    pub fn set_post_processor(&mut self, post_processor: Option<PostProcessor>) -> Result<()> {
+        if let Some(processor) = &post_processor {
+            let tokens = processor.single.special_tokens().chain(processor.pair.special_tokens());
+            if let Some(missing) = tokens.into_iter().find(|token| self.get_index(token).is_none()) {
+                return Err(TokenizerError::UnknownToken(missing.to_string()));
+            }
+        }
+        self.post_processor = post_processor;
+        Ok(())
     }
 
-    pub fn get_index(&self, token: &str) -> Option<usize> {
-        self.token_to_index.get(token).copied()
+    pub fn pre_tokenize<'a>(&self, text: &'a str) -> Vec<(usize, &'a str)> {
+        self.pre_tokenizer.pre_tokenize(text)
     }
 
-    pub fn get_tokens(&self, indices: &Vec<usize>) -> Vec<String> {
-        indices.iter().map(|&idx| self.get_token(idx).unwrap()).collect()
+    pub fn is_cased(&self) -> bool {
+        self.normalizer.is_cased()
     }
-    
-    pub fn get_indices(&self, tokens: &Vec<String>) -> Vec<usize> {
-        tokens.iter().map(|token| self.get_index(token).unwrap()).collect()
+
+    // Register tokens at the end of the vocabulary and return their ids. Tokens already in the
+    // vocabulary keep their id, so existing ids never change
+    pub fn add_tokens(&mut self, tokens: Vec<AddedToken>) -> Result<Vec<usize>> {
+        let mut indices = Vec::with_capacity(tokens.len());
+        for mut token in tokens {
+            if let Some(existing) = self.config.added_tokens.iter().find(|added| added.value == token.value) {
+                indices.push(existing.index);
+                continue;
+            }
+            token.index = self.get_index(&token.value).unwrap_or_else(|| {
+                self.vocabulary.push(token.value.clone());
+                self.vocabulary.len() - 1
+            });
+            indices.push(token.index);
+            self.config.added_tokens.push(token);
+        }
+        self.build_index()?;
+        Ok(indices)
     }
-    
-    pub fn clean_text(text: &str) -> String {
-        text.to_lowercase()
+
+    // Lazily encode text read from `reader`, which must be UTF-8
+    pub fn encode_reader<R: io::Read>(&self, reader: R) -> EncodeStream<'_, ReadChunks<R>> {
+        EncodeStream::new(self, ReadChunks::new(reader))
+    }
+
+    // Lazily encode text split into arbitrary chunks - the ids do not depend on where chunks end,
+    // unless a line outgrows the stream buffer (see `EncodeStream`)
+    pub fn encode_iter<'a, I, S>(&'a self, chunks: I) -> impl Iterator<Item = usize> + 'a
+    where
+        I: IntoIterator<Item = S>,
+        I::IntoIter: 'a,
+        S: Into<String>,
+    {
+        let chunks = chunks.into_iter().map(|chunk| Ok::<_, std::convert::Infallible>(chunk.into()));
+        EncodeStream::new(self, chunks).map(|id| match id {
+            Ok(id) => id,
+            Err(never) => match never {},
+        })
+    }
+
+    // Split raw input into (byte offset, segment, token index) at control tokens and added tokens
+    // matched before normalization. Control tokens and the unknown token are left as text in `Plain` mode
+    pub(crate) fn split_special_tokens<'a>(&self, text: &'a str) -> Vec<(usize, &'a str, Option<usize>)> {
+        split_special_tokens(text, &self.config, self.special_token_mode)
+    }
+
+    // Encode normalized text, matching added tokens that apply after normalization
+    fn encode_normalized(&self, text: &str, dropout: Option<&Dropout>) -> Vec<(usize, usize)> {
+        let patterns: Vec<String> = self.config.added_tokens.iter()
+            .map(|token| if token.normalized { self.normalizer.normalize(&token.value) } else { String::new() })
+            .collect();
+        let matchers = added_matchers(&self.config.added_tokens, &patterns, true, self.special_token_mode);
+        if matchers.is_empty() {
+            return self.encode_symbols(text, dropout);
+        }
+
+        let mut symbols = Vec::new();
+        let mut last = 0;
+        for (start, end, index) in find_matches(text, &matchers) {
+            symbols.extend(self.encode_symbols(&text[last..start], dropout));
+            symbols.push((index, end - start));
+            last = end;
+        }
+        symbols.extend(self.encode_symbols(&text[last..], dropout));
+        symbols
+    }
+
+    // The number of ids `tokenize` returns, without building them
+    pub fn count_tokens(&self, input_text: &str) -> usize {
+        if !self.counts_in_place() {
+            return self.tokenize(input_text).len();
+        }
+        self.count_prefix(input_text, usize::MAX).0
+    }
+
+    // The longest prefix of `input_text` that encodes to at most `n` tokens, cut where a token ends
+    pub fn truncate_to_tokens<'a>(&self, input_text: &'a str, n: usize) -> &'a str {
+        match self.count_prefix(input_text, n) {
+            (_, _, true) => input_text,
+            (_, end, false) => &input_text[..end],
+        }
+    }
+
+    pub fn count_tokens_batch<T: AsRef<str> + Sync>(&self, texts: &[T]) -> Vec<usize> {
+        texts.par_iter().map(|text| self.count_tokens(text.as_ref())).collect()
+    }
+
+    pub fn truncate_to_tokens_batch<'a, T: AsRef<str> + Sync>(&self, texts: &'a [T], n: usize) -> Vec<&'a str> {
+        texts.par_iter().map(|text| self.truncate_to_tokens(text.as_ref(), n)).collect()
+    }
+
+    // Counting reads the input in place for greedy encoding without pre-tokenization, with a
+    // normalizer that at most lowercases and no added tokens matched after normalization.
+    // Anything else is encoded in full
+    fn counts_in_place(&self) -> bool {
+        self.mode == EncodingMode::Greedy
+            && self.pre_tokenizer.is_empty()
+            && self.normalizer.steps.iter().all(|step| *step == NormalizerStep::Lowercase)
+            && !self.config.added_tokens.iter().any(|token| token.normalized)
+    }
+
+    // Count up to `limit` tokens, returning the count, the byte offset where they end, and whether
+    // they cover the whole text
+    fn count_prefix(&self, input_text: &str, limit: usize) -> (usize, usize, bool) {
+        if !self.counts_in_place() {
+            let encoding = self.encode_sequence(input_text, None);
+            if encoding.len() <= limit {
+                return (encoding.len(), input_text.len(), true);
+            }
+            // Tokens of one char, such as its byte tokens, share its offsets - cut before the char
+            // unless all of them fit
+            let offsets = &encoding.offsets;
+            let mut count = limit;
+            while count > 0 && offsets[count].0 < offsets[count - 1].1 {
+                count -= 1;
+            }
+            let end = count.checked_sub(1).map_or(0, |last| offsets[last].1);
+            return (count, end, false);
+        }
+
+        let (mut count, mut end) = (0, 0);
+        for (start, segment, special) in self.split_special_tokens(input_text) {
+            let (segment_count, segment_end, done) = match special {
+                Some(_) if count < limit => (1, segment.len(), true),
+                Some(_) => (0, 0, false),
+                None => self.count_segment(segment, limit - count),
+            };
+            if segment_end > 0 {
+                end = start + segment_end;
+            }
+            count += segment_count;
+            if !done {
+                return (count, end, false);
+            }
+        }
+        (count, end, true)
+    }
+
+    // Walk the trie over the normalized chars of `text` as `tokenize_greedy` would, stopping
+    // before the token that would go over `limit`
+    fn count_segment(&self, text: &str, limit: usize) -> (usize, usize, bool) {
+        let lowercase = !self.normalizer.is_cased();
+        let (mut count, mut end) = (0, 0);
+        let (mut position, mut skip) = (0, 0);
+        loop {
+            let mut chars = source_chars(text, position, skip, lowercase);
+            let Some(first) = chars.next() else { return (count, end, true) };
+            let found = self.vocabulary_trie.longest_match(std::iter::once(first).chain(chars).map(|source| (source.c, source)));
+            // An unknown char is one unknown token, or one byte token per UTF-8 byte
+            let (tokens, last) = match found {
+                Some(last) => (1, last),
+                None if self.byte_fallback => (first.c.len_utf8(), first),
+                None => (1, first),
+            };
+            if count + tokens > limit {
+                return (count, end, false);
+            }
+            count += tokens;
+            // A token can end partway through the chars one original char lowercases to, and the
+            // text can only be cut after that char
+            (position, skip) = if last.last {
+                end = last.end;
+                (last.end, 0)
+            } else {
+                (last.start, last.sub + 1)
+            };
+        }
+    }
+
+    // Encode a text and apply the post-processor template, if any
+    pub fn encode(&self, input_text: &str) -> Encoding {
+        let encoding = self.encode_sequence(input_text, None);
+        match &self.post_processor {
+            Some(processor) => processor.process(encoding, None, |token| self.template_token(token)),
+            None => encoding,
+        }
+    }
+
+    // Encode a pair of texts - without a post-processor they are concatenated with type ids 0 and 1
+    pub fn encode_pair(&self, first: &str, second: &str) -> Encoding {
+        let first = self.encode_sequence(first, None);
+        let mut second = self.encode_sequence(second, None);
+        match &self.post_processor {
+            Some(processor) => processor.process(first, Some(second), |token| self.template_token(token)),
+            None => {
+                second.type_ids = vec![1; second.len()];
+                Encoding::concat(&[first, second])
+            }
+        }
+    }
+
+    // BPE-dropout: encode with the merge rules, skipping each candidate merge with probability `p`.
+    // The same seed always gives the same segmentation, and `p = 0` gives the merge-mode encoding
+    pub fn encode_with_dropout(&self, input_text: &str, p: f64, seed: u64) -> Result<Encoding> {
+        let dropout = Dropout::new(p, seed)?;
+        Ok(self.encode_dropout(input_text, &dropout))
+    }
+
+    // Draw `n` segmentations of a text with BPE-dropout, one seed for the whole draw
+    pub fn sample_encodings(&self, input_text: &str, n: usize, p: f64, seed: u64) -> Result<Vec<Encoding>> {
+        let dropout = Dropout::new(p, seed)?;
+        Ok((0..n).map(|_| self.encode_dropout(input_text, &dropout)).collect())
+    }
+
+    // Up to `n` distinct segmentations: the merge-mode encoding first, then dropout samples with
+    // the fewest tokens first. Gives up after `16 * n` draws when the text has few segmentations
+    pub fn encode_nbest(&self, input_text: &str, n: usize, p: f64, seed: u64) -> Result<Vec<Encoding>> {
+        let dropout = Dropout::new(p, seed)?;
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let mut best = vec![self.encode_dropout(input_text, &Dropout::new(0.0, seed)?)];
+        for _ in 0..16 * n {
+            if best.len() >= n {
+                break;
+            }
+            let sample = self.encode_dropout(input_text, &dropout);
+            if best.iter().all(|encoding| encoding.ids != sample.ids) {
+                best.push(sample);
+            }
+        }
+        best.truncate(n);
+        best[1..].sort_by_key(|encoding| encoding.len());
+        Ok(best)
+    }
+
+    fn encode_dropout(&self, input_text: &str, dropout: &Dropout) -> Encoding {
+        let encoding = self.encode_sequence(input_text, Some(dropout));
+        match &self.post_processor {
+            Some(processor) => processor.process(encoding, None, |token| self.template_token(token)),
+            None => encoding,
+        }
+    }
+
+    fn template_token(&self, token: &str) -> Token {
+        let index = self.get_index(token).unwrap_or(self.config.unknown.index);
+        Token { value: token.to_string(), index }
+    }
+
+    fn encode_sequence(&self, input_text: &str, dropout: Option<&Dropout>) -> Encoding {
+        let segments = self.split_special_tokens(input_text);
+        if let [(_, text, None)] = segments[..] {
+            return self.encode_text(text, dropout);
+        }
+
+        // Shift each segment's offsets and word ids to follow the segments before it
+        let mut parts = Vec::with_capacity(segments.len());
+        let (mut chars, mut words) = (0, 0);
+        for (start, segment, special) in segments {
+            let char_count = segment.chars().count();
+            let mut part = match special {
+                Some(index) => {
+                    let value = self.get_token(index).unwrap_or_else(|| segment.to_string());
+                    let mut part = Encoding::special(&Token { value, index });
+                    part.offsets = vec![(0, segment.len())];
+                    part.char_offsets = vec![(0, char_count)];
+                    // Non-special added tokens count as words
+                    if !self.config.is_control_token(index) {
+                        part.special_tokens_mask = vec![0];
+                        part.word_ids = vec![Some(0)];
+                    }
+                    part
+                }
+                None => self.encode_text(segment, dropout),
+            };
+            part.offsets.iter_mut().for_each(|offset| *offset = (offset.0 + start, offset.1 + start));
+            part.char_offsets.iter_mut().for_each(|offset| *offset = (offset.0 + chars, offset.1 + chars));
+            let part_words = part.word_ids.iter().flatten().max().map_or(0, |&word| word + 1);
+            part.word_ids.iter_mut().flatten().for_each(|word| *word += words);
+            words += part_words;
+            chars += char_count;
+            parts.push(part);
+        }
+        Encoding::concat(&parts)
+    }
+
+    fn encode_text(&self, input_text: &str, dropout: Option<&Dropout>) -> Encoding {
+        let normalized = self.normalizer.normalize_aligned(input_text);
+        let text = &normalized.normalized;
+        let symbols = self.encode_normalized(text, dropout);
+
+        // Offsets are found in the normalized text, then mapped back onto the input
+        let mut normalized_offsets = Vec::with_capacity(symbols.len());
+        let mut start = 0;
+        for &(_, length) in symbols.iter() {
+            normalized_offsets.push((start, start + length));
+            start += length;
+        }
+        let offsets: Vec<(usize, usize)> = normalized_offsets.iter()
+            .map(|&(start, end)| normalized.original_range(start, end))
+            .collect();
+
+        let ids: Vec<usize> = symbols.iter().map(|&(index, _)| index).collect();
+        let special_tokens_mask = ids.iter().map(|&index| self.config.is_control_token(index) as u8).collect();
+        Encoding {
+            tokens: ids.iter().map(|&index| self.vocabulary[index].clone()).collect(),
+            char_offsets: char_offsets(input_text, &offsets),
+            special_tokens_mask,
+            attention_mask: vec![1; ids.len()],
+            word_ids: self.word_ids(text, &normalized_offsets),
+            type_ids: vec![0; ids.len()],
+            offsets,
+            ids,
+            overflowing: Vec::new(),
+        }
+    }
+
+    // With a post-processor, texts are truncated before the template is applied so its tokens are kept.
+    // Fails if the truncation length leaves no room for a text next to the template tokens
+    pub fn encode_batch<T: AsRef<str> + Sync>(&self, texts: &[T], options: &BatchOptions) -> Result<BatchEncoding> {
+        let encodings = match (options.truncation_length(), &self.post_processor) {
+            (Some(max_length), Some(_)) => {
+                let truncation = Truncation { max_length, strategy: TruncationStrategy::LongestFirst, stride: 0 };
+                texts.par_iter().map(|text| {
+                    let mut encoding = self.encode_truncated(text.as_ref(), None, &truncation)?;
+                    encoding.overflowing.clear();
+                    Ok(encoding)
+                }).collect::<Result<Vec<_>>>()?
+            }
+            _ => texts.par_iter().map(|text| self.encode(text.as_ref())).collect(),
+        };
+        Ok(BatchEncoding::new(encodings, options, &self.config.pad))
+    }
+
+    // Encode a text, or a pair of texts, into windows of at most `truncation.max_length` tokens.
+    // Every window is wrapped by the post-processor template, or `sot $A eot` and `sot $A eot $B eot`
+    // without one; the first window is returned and the rest are in `overflowing`. For a pair, every
+    // window of the first text is paired with every window of the second. Fails if `max_length` leaves
+    // no room for a non-empty text once the template tokens are added
+    pub fn encode_truncated(&self, first: &str, second: Option<&str>, truncation: &Truncation) -> Result<Encoding> {
+        let first = self.encode_sequence(first, None);
+        let second = second.map(|second| self.encode_sequence(second, None));
+        let processor = match &self.post_processor {
+            Some(processor) => processor.clone(),
+            None => PostProcessor::wrap(&self.config.sot.value, &self.config.eot.value),
+        };
+        let budget = truncation.max_length.saturating_sub(processor.added_tokens(second.is_some()));
+
+        let (first_length, second_length) = match &second {
+            None => (budget, 0),
+            Some(second) => {
+                let (a, b) = (first.len(), second.len());
+                match truncation.strategy {
+                    TruncationStrategy::OnlyFirst => (budget.saturating_sub(b), b),
+                    TruncationStrategy::OnlySecond => (a, budget.saturating_sub(a)),
+                    // Trim the longer sequence one token at a time, the second on ties
+                    TruncationStrategy::LongestFirst => {
+                        let first_length = a.min(budget.saturating_sub(b.min(budget / 2)));
+                        (first_length, b.min(budget - first_length))
+                    }
+                }
+            }
+        };
+        let empty = |sequence: Option<&Encoding>| sequence.is_none_or(Encoding::is_empty);
+        if (first_length == 0 && !first.is_empty()) || (second_length == 0 && !empty(second.as_ref())) {
+            return Err(TokenizerError::InvalidConfig(format!(
+                "max_length {} leaves no room for the input after {} template tokens",
+                truncation.max_length, processor.added_tokens(second.is_some()),
+            )));
+        }
+
+        let process = |first: &Encoding, second: Option<&Encoding>| {
+            processor.process(first.clone(), second.cloned(), |token| self.template_token(token))
+        };
+        let mut windows = Vec::new();
+        let first_windows = first.windows(first_length, truncation.stride);
+        match &second {
+            None => windows.extend(first_windows.iter().map(|window| process(window, None))),
+            Some(second) => {
+                // Pair every window of the first sequence with every window of the second
+                let second_windows = second.windows(second_length, truncation.stride);
+                for window in first_windows.iter() {
+                    windows.extend(second_windows.iter().map(|second_window| process(window, Some(second_window))));
+                }
+            }
+        }
+
+        let mut encoding = windows.remove(0);
+        encoding.overflowing = windows;
+        Ok(encoding)
     }
 
-    pub fn tokenize(&self, input_text: &str) -> Vec<usize> {
-        let text = Self::clean_text(input_text);
-        let mut tokens = Vec::new();
+    // Words are pre-tokenized pieces, or runs of non-whitespace without a pre-tokenizer
+    fn word_ids(&self, text: &str, offsets: &[(usize, usize)]) -> Vec<Option<usize>> {
+        if !self.pre_tokenizer.is_empty() {
+            let starts: Vec<usize> = self.pre_tokenize(text).into_iter().map(|(start, _)| start).collect();
+            return offsets.iter().map(|&(start, _)| starts.partition_point(|&s| s <= start).checked_sub(1)).collect();
+        }
+
+        let mut word_starts = Vec::new();
+        let mut in_word = false;
+        for (i, c) in text.char_indices() {
+            if !c.is_whitespace() && !in_word {
+                word_starts.push(i);
+            }
+            in_word = !c.is_whitespace();
+        }
+        offsets.iter().map(|&(start, end)| {
+            // Byte tokens can split a character, and only ever encode non-whitespace
+            let first = if text.is_char_boundary(start) && text.is_char_boundary(end) {
+                start + text[start..end].char_indices().find(|(_, c)| !c.is_whitespace())?.0
+            } else {
+                start
+            };
+            word_starts.partition_point(|&s| s <= first).checked_sub(1)
+        }).collect()
+    }
+
+    // Encode cleaned text into (index, byte length) symbols covering the whole input.
+    // Dropout always encodes with the merge rules, whatever the mode
+    fn encode_symbols(&self, text: &str, dropout: Option<&Dropout>) -> Vec<(usize, usize)> {
+        let greedy = self.mode == EncodingMode::Greedy && dropout.is_none();
+        if self.pre_tokenizer.is_empty() {
+            return match greedy {
+                true => self.tokenize_greedy(text),
+                false => self.tokenize_merge(text, dropout),
+            };
+        }
+
+        // Tokens never cross pre-tokenized piece boundaries
+        let mut symbols = Vec::new();
+        for (_, piece) in self.pre_tokenizer.pre_tokenize(text) {
+            match greedy {
+                true => symbols.extend(self.tokenize_greedy(piece)),
+                false => symbols.extend(self.merge_word(piece, dropout)),
+            }
+        }
+        symbols
+    }
+
+    fn tokenize_greedy(&self, text: &str) -> Vec<(usize, usize)> {
+        let mut symbols = Vec::new();
         let mut start = 0;
     
-        while start < text.len() {
-            if let Some((length, substr)) = self.vocabulary_trie.find_longest_prefix(&text[start..]) {
-                // Add the longest match and update the start position
-                tokens.push(self.get_index(substr).unwrap());
+        while let Some(c) = text[start..].chars().next() {
+            let found = self.vocabulary_trie.find_longest_prefix(&text[start..])
+                .and_then(|(length, substr)| Some((self.get_index(substr)?, length)));
+            if let Some((index, length)) = found {
+                // Add the longest match and advance by its length in bytes
+                symbols.push((index, length));
                 start += length;
             } else {
-                // If no token is found, append unknown token
-                tokens.push(self.config.unknown.index);
-                start += 1;
+                // If no token is found, fall back to bytes or the unknown token
+                self.unknown_symbols(c, &mut symbols);
+                start += c.len_utf8();
             }
         }
-        tokens
+        symbols
     }
 
-    pub fn detokenize(&self, indices: &[usize]) -> String {
-        let mut result = String::new();
-        let mut tokens = Vec::new();
-    
-        // Collect tokens from indices
-        for &idx in indices.iter() {
-            if let Some(token) = self.get_token(idx) {
-                tokens.push(token);
+    fn tokenize_merge(&self, text: &str, dropout: Option<&Dropout>) -> Vec<(usize, usize)> {
+        // Without a pre-tokenizer training joins words with single spaces, so merges may span them - only split at line breaks
+        let mut symbols = Vec::new();
+        for word in text.split_inclusive(['\n', '\r']) {
+            let (word, line_break) = match word.char_indices().last() {
+                Some((i, c)) if c == '\n' || c == '\r' => (&word[..i], Some(c)),
+                _ => (word, None),
+            };
+            symbols.extend(self.merge_word(word, dropout));
+            if let Some(c) = line_break {
+                self.char_symbols(c, &mut symbols);
             }
         }
-    
-        for i in 0..tokens.len() {
-            let token = &tokens[i];
-            let next_token = tokens.get(i + 1).cloned().unwrap_or_default();
-    
-            if !self.config.is_eos(token) {
-                result.push_str(token);
-            } else if !self.config.is_special_token(&next_token) {
-                result.push(' ');
+        symbols
+    }
+
+    fn merge_word(&self, word: &str, dropout: Option<&Dropout>) -> Vec<(usize, usize)> {
+        let mut symbols = Vec::with_capacity(word.len());
+        if self.byte_level {
+            let unknown = self.config.unknown.index;
+            symbols.extend(word.bytes().map(|byte| (self.merge_ranks.byte(byte).unwrap_or(unknown), 1)));
+        } else {
+            for c in word.chars() {
+                self.char_symbols(c, &mut symbols);
             }
         }
-    
-        result
+        match dropout {
+            Some(dropout) => self.merge_ranks.apply_with(&symbols, || dropout.keep()),
+            None => self.merge_ranks.apply(&symbols),
+        }
+    }
+
+    // Push the (index, byte length) symbols for a single character
+    fn char_symbols(&self, c: char, symbols: &mut Vec<(usize, usize)>) {
+        match self.get_index(c.encode_utf8(&mut [0; 4])) {
+            Some(index) => symbols.push((index, c.len_utf8())),
+            None => self.unknown_symbols(c, symbols),
+        }
     }
 
-    pub fn train_cpu(source: &str, iterations: usize, output_filepath: &str, start_filepath: Option<&str>) -> Self {
+    fn unknown_symbols(&self, c: char, symbols: &mut Vec<(usize, usize)>) {
+        if self.byte_fallback {
+            for &byte in c.encode_utf8(&mut [0; 4]).as_bytes() {
+                let index = self.get_index(&byte_token(byte)).unwrap_or(self.config.unknown.index);
+                symbols.push((index, 1));
+            }
+        } else {
+            symbols.push((self.config.unknown.index, c.len_utf8()));
+        }
+    }
+
+    pub fn set_decoder(&mut self, decoder: DecoderPipeline) {
+        self.decoder = decoder;
+    }
+
+    pub fn train_cpu(source: &str, iterations: usize, output_filepath: &str, start_filepath: Option<&str>) -> Result<Self> {
+        Self::train_cpu_with_options(source, iterations, output_filepath, start_filepath, TrainOptions::default())
+    }
+
+    pub fn train_cpu_with_options(
+        source: &str,
+        iterations: usize,
+        output_filepath: &str,
+        start_filepath: Option<&str>,
+        options: TrainOptions,
+    ) -> Result<Self> {
+        Self::train_cpu_to(source, iterations, Some(output_filepath), start_filepath, options)
+    }
+
+    // Train without saving, for models derived from BPE training
+    pub(crate) fn train_cpu_unsaved(source: &str, iterations: usize, options: TrainOptions) -> Result<Self> {
+        Self::train_cpu_to(source, iterations, None, None, options)
+    }
+
+    fn train_cpu_to(
+        source: &str,
+        iterations: usize,
+        output_filepath: Option<&str>,
+        start_filepath: Option<&str>,
+        mut options: TrainOptions,
+    ) -> Result<Self> {
         // Train tokenizer on CPU using byte pair encoding
         let start_time = Instant::now();
         let mut config = TokenConfig::new();
         let mut merge_rules: Vec<(String, String)>;
         let mut token_list: Vec<String>;
-        let mut token_indices: Vec<usize>;
         let mut token_map: HashMap<String, usize>;
 
         // Load existing tokenizer if provided
         if let Some(start) = start_filepath {
-            let tokenizer = Tokenizer::load(start).expect("Failed to load tokenizer");
+            let mut tokenizer = Tokenizer::load(start)?;
+            if options.byte_fallback && !tokenizer.byte_fallback {
+                tokenizer.enable_byte_fallback()?;
+            }
+            options.byte_fallback = tokenizer.byte_fallback;
+            options.normalizer = tokenizer.normalizer.clone();
+            options.pre_tokenizer = tokenizer.pre_tokenizer.clone();
+            options.post_processor = tokenizer.post_processor.clone();
+            config = tokenizer.config.clone();
             merge_rules = tokenizer.get_merge_rules();
-            token_list = tokenizer.get_vocabulary().into_iter().collect();
-
-            // Rebuild token_map and token_indices using only space to separate words
+            token_list = tokenizer.get_vocabulary();
             token_map = tokenizer.token_to_index.clone();
-            token_indices = source.split_whitespace().flat_map(|word| {
-                word.chars().map(|c| token_map.get(&c.to_string()).unwrap().to_owned()).chain(Some(config.space.index))
-            }).collect();
-            token_indices.pop(); 
         } else {
             // Initialize tokenizer from scratch
             merge_rules = Vec::new();
             token_list = config.get_values().iter().map(|v| v.to_string()).collect();
-
-            // Split the source into words and handle end of words
-            token_indices = Vec::with_capacity(source.len());
             token_map = HashMap::new();
             let values = config.get_values();
             let indices = config.get_indices();
@@ -156,22 +709,36 @@ impl Tokenizer {
                 token_map.insert(value.clone(), index);
             }
 
-            // Utilize a buffer for character conversion to reduce allocation
-            let mut char_buffer = String::with_capacity(4); // Capacity for a single character string
+            // Reserve byte tokens directly after the special tokens
+            if options.byte_fallback {
+                for token in byte_tokens() {
+                    token_map.insert(token.clone(), token_list.len());
+                    token_list.push(token);
+                }
+            }
+        }
+
+        // Split the source into words and handle end of words
+        let source = options.normalizer.normalize(source);
+        let mut token_indices: Vec<usize> = Vec::with_capacity(source.len());
+        let mut char_buffer = String::with_capacity(4); // Capacity for a single character string
+        let mut push_chars = |text: &str, token_indices: &mut Vec<usize>| {
+            for c in text.chars() {
+                char_buffer.clear();
+                char_buffer.push(c);
+
+                let index = *token_map.entry(char_buffer.clone()).or_insert_with(|| {
+                    let new_index = token_list.len();
+                    token_list.push(char_buffer.clone());
+                    new_index
+                });
+                token_indices.push(index);
+            }
+        };
 
+        if options.pre_tokenizer.is_empty() {
             for word in source.split_whitespace() {
-                for c in word.chars() {
-                    char_buffer.clear();
-                    char_buffer.push(c);
-                    char_buffer.make_ascii_lowercase();
-
-                    let index = *token_map.entry(char_buffer.clone()).or_insert_with(|| {
-                        let new_index = token_list.len();
-                        token_list.push(char_buffer.clone());
-                        new_index
-                    });
-                    token_indices.push(index);
-                }
+                push_chars(word, &mut token_indices);
                 token_indices.push(config.space.index);
             }
 
@@ -181,6 +748,12 @@ impl Tokenizer {
                     token_indices.pop();
                 }
             }
+        } else {
+            // Keep pieces intact and separate them so no pair spans a boundary
+            for (_, piece) in options.pre_tokenizer.pre_tokenize(&source) {
+                push_chars(piece, &mut token_indices);
+                token_indices.push(PIECE_BOUNDARY);
+            }
         }
         println!("Init time: {:?}", start_time.elapsed().as_secs_f32());
 
@@ -192,7 +765,9 @@ impl Tokenizer {
                 let mut local_map = HashMap::new();
                 for window in chunk.windows(2) {
                     if let [a, b] = window {
-                        *local_map.entry((*a, *b)).or_insert(0) += 1;
+                        if *a != PIECE_BOUNDARY && *b != PIECE_BOUNDARY {
+                            *local_map.entry((*a, *b)).or_insert(0) += 1;
+                        }
                     }
                 }
                 // Merge local map into the global DashMap
@@ -207,9 +782,12 @@ impl Tokenizer {
                 .unwrap_or((usize::MAX, usize::MAX));
 
             if first != usize::MAX && second != usize::MAX {
+                // Different merges can produce the same string - reuse its index if so
                 let new_token = format!("{}{}", token_list[first], token_list[second]);
-                let new_index = token_list.len();
-                token_list.push(new_token);
+                let new_index = *token_map.entry(new_token.clone()).or_insert_with(|| {
+                    token_list.push(new_token);
+                    token_list.len() - 1
+                });
                 merge_rules.push((token_list[first].clone(), token_list[second].clone()));
             
                 // Update token_indices
@@ -233,51 +811,52 @@ impl Tokenizer {
             }
 
             // Save every 50 iterations
-            if i % 50 == 0 {
-                let vocabulary: HashSet<String> = token_list.clone().into_iter().collect();
-                config.set_indices(Self::extract_indices(&vocabulary, &config));
-                let tokenizer = Tokenizer::new(vocabulary, merge_rules.clone(), config.clone());
-                tokenizer.save(output_filepath).unwrap();
+            if let Some(path) = output_filepath.filter(|_| i % 50 == 0) {
+                let tokenizer = Self::from_training(token_list.clone(), merge_rules.clone(), config.clone(), &options)?;
+                tokenizer.save(path)?;
             }
 
             println!("Iteration {} time: {:?}", i, iter_time.elapsed().as_secs_f32());
-            io::stdout().flush().unwrap();
+            io::stdout().flush()?;
         }
     
-        let vocabulary: HashSet<String> = token_list.into_iter().collect();
-        config.set_indices(Self::extract_indices(&vocabulary, &config));
-        let trained_tokenizer = Tokenizer::new(vocabulary, merge_rules, config);
-        trained_tokenizer.save(output_filepath).unwrap();
+        let trained_tokenizer = Self::from_training(token_list, merge_rules, config, &options)?;
+        if let Some(path) = output_filepath {
+            trained_tokenizer.save(path)?;
+        }
         println!("Total time: {:?}", start_time.elapsed().as_secs_f32());
 
-        trained_tokenizer
+        Ok(trained_tokenizer)
     } 
  
-    fn extract_indices(token_list: &HashSet<String>, config: &TokenConfig) -> Vec<usize> {
-        let mut indices = Vec::new();
-        for token in config.get_values() {
-            // get index from hashset
-            let index = token_list.iter().position(|t| t == &token).unwrap();
-            indices.push(index);
-        }
-        indices
+    fn from_training(token_list: Vec<String>, merge_rules: Vec<(String, String)>, config: TokenConfig, options: &TrainOptions) -> Result<Self> {
+        let mut tokenizer = Tokenizer::new(token_list, merge_rules, config)?;
+        tokenizer.byte_fallback = options.byte_fallback;
+        tokenizer.normalizer = options.normalizer.clone();
+        tokenizer.pre_tokenizer = options.pre_tokenizer.clone();
+        tokenizer.post_processor = options.post_processor.clone();
+        Ok(tokenizer)
+    }
+
+    pub fn process_dataset(dir: &str) -> Result<String> {
+        Self::process_dataset_with_options(dir, &TrainOptions::default())
     }
 
-    pub fn process_dataset(dir: &str) -> String {
+    pub fn process_dataset_with_options(dir: &str, options: &TrainOptions) -> Result<String> {
         // Process all .txt files in the provided directory into a single string
         let mut text = String::new();
         let mut i = 0;
-        for entry in std::fs::read_dir(dir).unwrap() {
-            let file = entry.unwrap();
+        for entry in std::fs::read_dir(dir)? {
+            let file = entry?;
             let path = file.path();
             if path.is_file() {
                 println!("Reading file: {:?}", file.file_name());
                 match std::fs::read(&path) {
                     Ok(bytes) => {
                         let source_text = String::from_utf8_lossy(&bytes);
-                        let clean_text = Tokenizer::clean_text(&source_text);
+                        let clean_text = options.normalizer.normalize(&source_text);
                         if i > 0 {
-                            text.push_str(" ");
+                            text.push(' ');
                         }
                         text.push_str(&clean_text);
                     },
@@ -289,29 +868,110 @@ impl Tokenizer {
             }
             i += 1;
         }
-        text
+        Ok(text)
+    }
+
+    // Byte tokens are only reached through byte fallback, so text spelling `<0x41>` stays text
+    pub fn build_trie(&mut self) {
+        let mut vocabulary_trie = TrieNode::new();
+        for (i, token) in self.vocabulary.iter().enumerate() {
+            let whole = self.config.is_control_token(i) || self.config.is_added_token(i) || i == self.config.unknown.index;
+            if !whole && parse_byte_tokens(token).is_none() {
+                vocabulary_trie.insert(token);
+            }
+        }
+        self.vocabulary_trie = vocabulary_trie;
     }
+}
 
-    pub fn save(&self, path: &str) -> std::io::Result<()> {
-        // Save tokenizer to a JSON file
-        let json = serde_json::to_string(&self)?;
-        std::fs::write(path, json)
+impl Model for Tokenizer {
+    fn vocabulary(&self) -> &[String] {
+        &self.vocabulary
     }
 
-    pub fn load(path: &str) -> std::io::Result<Self> {
-        // Load tokenizer from a JSON file
-        let data = std::fs::read_to_string(path)?;
-        let mut tokenizer: Tokenizer = serde_json::from_str(&data)?;
-        tokenizer.build_trie();
+    fn token_to_index(&self) -> &HashMap<String, usize> {
+        &self.token_to_index
+    }
 
-        Ok(tokenizer)
+    fn config(&self) -> &TokenConfig {
+        &self.config
     }
 
-    pub fn build_trie(&mut self) {
-        let mut vocabulary_trie = TrieNode::new();
-        for token in self.vocabulary.iter() {
-            vocabulary_trie.insert(token);
+    fn normalizer(&self) -> &NormalizerPipeline {
+        &self.normalizer
+    }
+
+    fn decoder(&self) -> &DecoderPipeline {
+        &self.decoder
+    }
+
+    fn special_token_mode(&self) -> SpecialTokenMode {
+        self.special_token_mode
+    }
+
+    fn set_special_token_mode(&mut self, mode: SpecialTokenMode) {
+        self.special_token_mode = mode;
+    }
+
+    fn encode_text(&self, text: &str, ids: &mut Vec<usize>) {
+        ids.extend(self.encode_symbols(text, None).into_iter().map(|(index, _)| index));
+    }
+
+    fn build_index(&mut self) -> Result<()> {
+        // Rebuild lookups from the ordered vocabulary so ids are identical on every load
+        if let Some(cased) = self.legacy_cased.take() {
+            if cased && self.normalizer == NormalizerPipeline::default() {
+                self.normalizer = NormalizerPipeline::cased();
+            }
         }
-        self.vocabulary_trie = vocabulary_trie;
+        self.token_to_index = index_tokens(&self.vocabulary);
+        self.config.resolve_indices(&self.token_to_index)?;
+        // Control, unknown and added tokens are only produced by matching them whole, never from merges or the trie
+        let control: Vec<usize> = self.config.control_tokens().iter().map(|token| token.index)
+            .chain([self.config.unknown.index])
+            .chain(self.config.added_tokens.iter().map(|token| token.index))
+            .collect();
+        let merge_ranks = match self.byte_level {
+            true => MergeRanks::from_bytes(&self.merge_rules, &self.vocabulary),
+            false => MergeRanks::new(&self.merge_rules, &self.token_to_index),
+        };
+        self.merge_ranks = merge_ranks.without_merged(&control);
+        self.build_trie();
+        Ok(())
     }
 }
+
+// A normalized char and the original char it came from
+#[derive(Clone, Copy)]
+struct SourceChar {
+    c: char,
+    // Byte range of the original char
+    start: usize,
+    end: usize,
+    // Position among the chars the original char normalizes to
+    sub: usize,
+    last: bool,
+}
+
+// The chars of `text` from byte `position` as `NormalizerStep::Lowercase` would produce them, or
+// unchanged, skipping the first `skip` chars
+fn source_chars(text: &str, position: usize, skip: usize, lowercase: bool) -> impl Iterator<Item = SourceChar> + '_ {
+    text[position..].char_indices().flat_map(move |(i, c)| {
+        let start = position + i;
+        let end = start + c.len_utf8();
+        let mut chars = [c; 3];
+        let mut count = 1;
+        if lowercase {
+            // Capital sigma becomes final sigma at the end of a word
+            let prev_alphabetic = text[..start].chars().next_back().is_some_and(char::is_alphabetic);
+            let next_alphabetic = text[end..].chars().next().is_some_and(char::is_alphabetic);
+            let c = if c == 'Σ' && prev_alphabetic && !next_alphabetic { 'ς' } else { c };
+            count = 0;
+            for lower in c.to_lowercase() {
+                chars[count] = lower;
+                count += 1;
+            }
+        }
+        (0..count).map(move |sub| SourceChar { c: chars[sub], start, end, sub, last: sub + 1 == count })
+    }).skip(skip)
+}
@@ -0,0 +1,14 @@
+use crate::tokenizer::{NormalizerPipeline, PostProcessor, PreTokenizerPipeline};
+
+
+#[derive(Debug, Clone, Default)]
+pub struct TrainOptions {
+    // Reserve `<0x00>`..`<0xFF>` tokens so unseen characters are encoded as bytes
+    pub byte_fallback: bool,
+    // Normalization applied to the corpus and saved with the trained tokenizer
+    pub normalizer: NormalizerPipeline,
+    // Pieces merges may not cross - an empty pipeline joins words with single spaces
+    pub pre_tokenizer: PreTokenizerPipeline,
+    // Special token template saved with the trained tokenizer
+    pub post_processor: Option<PostProcessor>,
+}
@@ -20,27 +20,39 @@ impl TrieNode {
     pub fn insert(&mut self, word: &str) {
         let mut current = self;
         for c in word.chars() {
-            current = current.children.entry(c).or_insert_with(TrieNode::new);
+            current = current.children.entry(c).or_default();
         }
         current.is_terminal = true;
     }
 
-    // Find the longest prefix that is a valid token
-    pub fn find_longest_prefix<'a>(&'a self, text: &'a str) -> Option<(usize, &str)> {
+    // Walk `chars` down the trie, yielding the value paired with the last char of every prefix that
+    // is a valid token, shortest first. The other lookups are built on this walk
+    pub fn matches<'a, T: 'a>(&'a self, chars: impl Iterator<Item = (char, T)> + 'a) -> impl Iterator<Item = T> + 'a {
         let mut current = self;
-        let mut last_valid_length = None;
-        let mut chars = text.chars().enumerate();
-
-        while let Some((index, c)) = chars.next() {
-            if let Some(next_node) = current.children.get(&c) {
-                current = next_node;
-                if current.is_terminal {
-                    last_valid_length = Some((index + 1, &text[0..index + 1]));
-                }
-            } else {
-                break;
-            }
-        }
-        last_valid_length
+        chars.map_while(move |(c, value)| {
+            current = current.children.get(&c)?;
+            Some(current.is_terminal.then_some(value))
+        }).flatten()
+    }
+
+    // Find the longest prefix that is a valid token, returning its length in bytes
+    pub fn find_longest_prefix<'a>(&'a self, text: &'a str) -> Option<(usize, &'a str)> {
+        self.matches(byte_ends(text)).last().map(|end| (end, &text[..end]))
+    }
+
+    // Lengths in bytes of every prefix that is a valid token, shortest first
+    pub fn find_prefixes(&self, text: &str) -> Vec<usize> {
+        self.matches(byte_ends(text)).collect()
     }
-}
\ No newline at end of file
+
+    // The value paired with the last char of the longest prefix that is a valid token, without
+    // needing the text in one string
+    pub fn longest_match<T>(&self, chars: impl Iterator<Item = (char, T)>) -> Option<T> {
+        self.matches(chars).last()
+    }
+}
+
+// Each char of `text` with the byte offset where it ends
+fn byte_ends(text: &str) -> impl Iterator<Item = (char, usize)> + '_ {
+    text.char_indices().map(|(index, c)| (c, index + c.len_utf8()))
+}
@@ -0,0 +1,54 @@
+use serde::{Serialize, Deserialize};
+
+use crate::tokenizer::TokenizerError;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    // Trim whichever sequence of a pair is longer
+    #[default]
+    LongestFirst,
+    OnlyFirst,
+    OnlySecond,
+}
+
+impl std::fmt::Display for TruncationStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TruncationStrategy::LongestFirst => write!(f, "longest_first"),
+            TruncationStrategy::OnlyFirst => write!(f, "only_first"),
+            TruncationStrategy::OnlySecond => write!(f, "only_second"),
+        }
+    }
+}
+
+impl std::str::FromStr for TruncationStrategy {
+    type Err = TokenizerError;
+
+    fn from_str(strategy: &str) -> Result<Self, Self::Err> {
+        match strategy.to_lowercase().as_str() {
+            "longest_first" => Ok(TruncationStrategy::LongestFirst),
+            "only_first" => Ok(TruncationStrategy::OnlyFirst),
+            "only_second" => Ok(TruncationStrategy::OnlySecond),
+            _ => Err(TokenizerError::InvalidConfig(format!("Unknown truncation strategy: {}", strategy))),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Truncation {
+    // Maximum tokens per window, boundary tokens included
+    pub max_length: usize,
+    #[serde(default)]
+    pub strategy: TruncationStrategy,
+    // Tokens shared between consecutive overflowing windows
+    #[serde(default)]
+    pub stride: usize,
+}
+
+impl Truncation {
+    pub fn new(max_length: usize) -> Self {
+        Truncation { max_length, strategy: TruncationStrategy::default(), stride: 0 }
+    }
+}
@@ -0,0 +1,412 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::Instant;
+use rayon::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::tokenizer::{SpecialTokenMode, TokenConfig, TrainOptions, TrieNode};
+use crate::tokenizer::{Normalizer, NormalizerPipeline};
+use crate::tokenizer::{PreTokenizer, PreTokenizerPipeline};
+use crate::tokenizer::DecoderPipeline;
+use crate::tokenizer::model::{index_tokens, Model};
+use crate::tokenizer::bytes::{byte_token, byte_tokens, parse_byte_token};
+use crate::tokenizer::error::{Result, TokenizerError};
+
+// Longest seed piece, in characters
+const MAX_PIECE_CHARS: usize = 16;
+// Seed vocabulary size as a multiple of the target size
+const SEED_FACTOR: usize = 8;
+// EM steps between pruning rounds
+const EM_STEPS: usize = 2;
+// Share of prunable pieces kept by each pruning round
+const SHRINK_FACTOR: f64 = 0.75;
+// Unknown characters score this far below the least likely token
+const UNKNOWN_PENALTY: f64 = 10.0;
+
+
+// Unigram language model, as in SentencePiece - text is split into the most likely sequence of tokens
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Unigram {
+    #[serde(skip_serializing, skip_deserializing)]
+    pub vocabulary_trie: TrieNode,
+    // Ordered vocabulary - the index of each token is its position in this list
+    pub vocabulary: Vec<String>,
+    // Log probability of each token, in vocabulary order
+    pub scores: Vec<f64>,
+    // Encode characters missing from the vocabulary as `<0xNN>` byte tokens instead of `<unk>`
+    #[serde(default)]
+    pub byte_fallback: bool,
+    #[serde(default)]
+    pub normalizer: NormalizerPipeline,
+    // Splits normalized text into pieces that tokens never cross
+    #[serde(default)]
+    pub pre_tokenizer: PreTokenizerPipeline,
+    #[serde(default)]
+    pub decoder: DecoderPipeline,
+    #[serde(default)]
+    pub special_token_mode: SpecialTokenMode,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub token_to_index: HashMap<String, usize>,
+    #[serde(skip_serializing, skip_deserializing)]
+    unknown_score: f64,
+    pub config: TokenConfig,
+}
+
+// A token spanning bytes `start..end` of a word
+type Edge = (usize, usize, usize);
+
+impl Unigram {
+    // Fails if a special token of `config` is missing from the vocabulary
+    pub fn new(vocabulary: Vec<String>, scores: Vec<f64>, config: TokenConfig) -> Result<Self> {
+        let mut unigram = Unigram {
+            vocabulary_trie: TrieNode::new(),
+            vocabulary,
+            scores,
+            byte_fallback: false,
+            normalizer: NormalizerPipeline::default(),
+            pre_tokenizer: PreTokenizerPipeline::default(),
+            decoder: DecoderPipeline::default(),
+            special_token_mode: SpecialTokenMode::default(),
+            token_to_index: HashMap::new(),
+            unknown_score: 0.0,
+            config,
+        };
+        unigram.build_index()?;
+        Ok(unigram)
+    }
+
+    pub fn set_decoder(&mut self, decoder: DecoderPipeline) {
+        self.decoder = decoder;
+    }
+
+    fn encode_word(&self, word: &str, ids: &mut Vec<usize>) {
+        for (start, end, index) in self.viterbi(word, None) {
+            if index == self.config.unknown.index && self.byte_fallback {
+                ids.extend(word[start..end].bytes().map(|byte| self.get_index(&byte_token(byte)).unwrap_or(index)));
+            } else {
+                ids.push(index);
+            }
+        }
+    }
+
+    fn score(&self, index: usize) -> f64 {
+        if index == self.config.unknown.index { self.unknown_score } else { self.scores[index] }
+    }
+
+    // Every token found in a word, ordered by start. Characters no token covers get an unknown edge
+    fn lattice(&self, word: &str, excluded: Option<usize>) -> Vec<Edge> {
+        let mut edges = Vec::new();
+        for (start, c) in word.char_indices() {
+            let mut covered = false;
+            for length in self.vocabulary_trie.find_prefixes(&word[start..]) {
+                let Some(index) = self.get_index(&word[start..start + length]) else { continue };
+                if Some(index) != excluded {
+                    covered |= length == c.len_utf8();
+                    edges.push((start, start + length, index));
+                }
+            }
+            if !covered {
+                edges.push((start, start + c.len_utf8(), self.config.unknown.index));
+            }
+        }
+        edges
+    }
+
+    // Most likely segmentation of a word, optionally without one token
+    fn viterbi(&self, word: &str, excluded: Option<usize>) -> Vec<Edge> {
+        let edges = self.lattice(word, excluded);
+        // (best score of a path ending here, last edge of that path)
+        let mut best = vec![(f64::NEG_INFINITY, usize::MAX); word.len() + 1];
+        best[0].0 = 0.0;
+        for (i, &(start, end, index)) in edges.iter().enumerate() {
+            let score = best[start].0 + self.score(index);
+            if score > best[end].0 {
+                best[end] = (score, i);
+            }
+        }
+
+        let mut path = Vec::new();
+        let mut end = word.len();
+        while end > 0 {
+            let edge = edges[best[end].1];
+            path.push(edge);
+            end = edge.0;
+        }
+        path.reverse();
+        path
+    }
+
+    // Add the expected count of each token in a word to `counts` and return the word's log likelihood
+    fn expected_counts(&self, word: &str, frequency: f64, counts: &mut [f64]) -> f64 {
+        let edges = self.lattice(word, None);
+        let mut alpha = vec![f64::NEG_INFINITY; word.len() + 1];
+        alpha[0] = 0.0;
+        for &(start, end, index) in edges.iter() {
+            alpha[end] = log_add(alpha[end], alpha[start] + self.score(index));
+        }
+        let mut beta = vec![f64::NEG_INFINITY; word.len() + 1];
+        beta[word.len()] = 0.0;
+        for &(start, end, index) in edges.iter().rev() {
+            beta[start] = log_add(beta[start], self.score(index) + beta[end]);
+        }
+
+        let likelihood = alpha[word.len()];
+        for &(start, end, index) in edges.iter() {
+            counts[index] += frequency * (alpha[start] + self.score(index) + beta[end] - likelihood).exp();
+        }
+        frequency * likelihood
+    }
+
+    // Train by EM over a seed vocabulary of frequent substrings, pruning the pieces whose removal
+    // costs the least likelihood until `vocab_size` is reached. Every character of the corpus is
+    // kept, so the vocabulary can end up larger than `vocab_size`
+    pub fn train(source: &str, vocab_size: usize, output_filepath: &str, options: TrainOptions) -> Result<Self> {
+        let start_time = Instant::now();
+        let config = TokenConfig::new();
+        let source = options.normalizer.normalize(source);
+
+        let mut frequencies: HashMap<&str, f64> = HashMap::new();
+        if options.pre_tokenizer.is_empty() {
+            for word in source.split_whitespace() {
+                *frequencies.entry(word).or_default() += 1.0;
+            }
+        } else {
+            for (_, piece) in options.pre_tokenizer.pre_tokenize(&source) {
+                *frequencies.entry(piece).or_default() += 1.0;
+            }
+        }
+        let mut words: Vec<(&str, f64)> = frequencies.into_iter().collect();
+        words.sort_by(|a, b| a.0.cmp(b.0));
+
+        let reserved = config.get_values().len() + if options.byte_fallback { 256 } else { 0 };
+        let target = vocab_size.saturating_sub(reserved);
+        let mut pieces = seed_pieces(&words, target * SEED_FACTOR);
+        println!("Seed time: {:?}, pieces: {}", start_time.elapsed().as_secs_f32(), pieces.len());
+
+        for round in 0.. {
+            let round_time = Instant::now();
+            for _ in 0..EM_STEPS {
+                pieces = Self::em_step(&pieces, &words, &config, &options)?;
+            }
+            if pieces.len() <= target {
+                break;
+            }
+            let pruned = Self::prune(&pieces, &words, target, &config, &options)?;
+            if pruned.len() == pieces.len() {
+                break;
+            }
+            pieces = pruned;
+
+            println!("Round {} time: {:?}, pieces: {}", round, round_time.elapsed().as_secs_f32(), pieces.len());
+            io::stdout().flush()?;
+        }
+
+        let unigram = Self::from_pieces(&pieces, config, &options)?;
+        unigram.save(output_filepath)?;
+        println!("Total time: {:?}", start_time.elapsed().as_secs_f32());
+        Ok(unigram)
+    }
+
+    // Special tokens, then byte tokens, then pieces from most to least likely
+    fn from_pieces(pieces: &[(String, f64)], config: TokenConfig, options: &TrainOptions) -> Result<Self> {
+        let mut vocabulary = config.get_values();
+        if options.byte_fallback {
+            vocabulary.extend(byte_tokens());
+        }
+        let floor = pieces.iter().map(|&(_, score)| score).fold(0.0, f64::min);
+        let mut scores = vec![floor; vocabulary.len()];
+        let mut reserved: HashMap<String, usize> = vocabulary.iter().cloned().enumerate().map(|(i, token)| (token, i)).collect();
+
+        let mut sorted: Vec<&(String, f64)> = pieces.iter().collect();
+        sorted.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        for (piece, score) in sorted {
+            // A piece that is already a special token, such as a space, takes over its score
+            match reserved.get(piece) {
+                Some(&index) => scores[index] = *score,
+                None => {
+                    reserved.insert(piece.clone(), vocabulary.len());
+                    vocabulary.push(piece.clone());
+                    scores.push(*score);
+                }
+            }
+        }
+
+        let mut unigram = Unigram::new(vocabulary, scores, config)?;
+        unigram.byte_fallback = options.byte_fallback;
+        unigram.normalizer = options.normalizer.clone();
+        unigram.pre_tokenizer = options.pre_tokenizer.clone();
+        Ok(unigram)
+    }
+
+    // Re-estimate scores from expected counts, dropping pieces that are almost never used.
+    // Characters are always kept so every word can still be encoded
+    fn em_step(pieces: &[(String, f64)], words: &[(&str, f64)], config: &TokenConfig, options: &TrainOptions) -> Result<Vec<(String, f64)>> {
+        let model = Self::from_pieces(pieces, config.clone(), options)?;
+        let size = model.vocab_size();
+        let (counts, likelihood) = words.par_iter()
+            .fold(|| (vec![0.0; size], 0.0), |(mut counts, likelihood), &(word, frequency)| {
+                let word_likelihood = model.expected_counts(word, frequency, &mut counts);
+                (counts, likelihood + word_likelihood)
+            })
+            .reduce(|| (vec![0.0; size], 0.0), |(mut a, a_likelihood), (b, b_likelihood)| {
+                a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                (a, a_likelihood + b_likelihood)
+            });
+        println!("Log likelihood: {}", likelihood);
+
+        let kept: Vec<(String, f64)> = pieces.iter()
+            .map(|(piece, _)| (piece.clone(), counts[model.token_to_index[piece]]))
+            .filter(|(piece, count)| *count >= 0.5 || piece.chars().count() == 1)
+            .map(|(piece, count)| (piece, count.max(0.5)))
+            .collect();
+        let total: f64 = kept.iter().map(|(_, count)| count).sum();
+        Ok(kept.into_iter().map(|(piece, count)| (piece, count.ln() - total.ln())).collect())
+    }
+
+    // Keep the pieces whose removal would lose the most likelihood - each removed piece is
+    // replaced by its own best segmentation wherever it was used
+    fn prune(pieces: &[(String, f64)], words: &[(&str, f64)], target: usize, config: &TokenConfig, options: &TrainOptions) -> Result<Vec<(String, f64)>> {
+        let model = Self::from_pieces(pieces, config.clone(), options)?;
+        let size = model.vocab_size();
+        let used = words.par_iter()
+            .fold(|| vec![0.0; size], |mut used, &(word, frequency)| {
+                for (_, _, index) in model.viterbi(word, None) {
+                    used[index] += frequency;
+                }
+                used
+            })
+            .reduce(|| vec![0.0; size], |mut a, b| {
+                a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                a
+            });
+
+        let mut losses: Vec<(f64, usize)> = pieces.par_iter().enumerate()
+            .filter(|(_, (piece, _))| piece.chars().count() > 1)
+            .map(|(i, (piece, score))| {
+                let index = model.token_to_index[piece];
+                let alternative: f64 = model.viterbi(piece, Some(index)).iter().map(|&(_, _, alt)| model.score(alt)).sum();
+                (used[index] * (score - alternative), i)
+            })
+            .collect();
+        losses.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        let characters = pieces.len() - losses.len();
+        let keep = target.saturating_sub(characters).max((losses.len() as f64 * SHRINK_FACTOR) as usize);
+        let mut kept: Vec<bool> = pieces.iter().map(|(piece, _)| piece.chars().count() == 1).collect();
+        for &(_, i) in losses.iter().take(keep) {
+            kept[i] = true;
+        }
+        Ok(pieces.iter().zip(kept).filter(|(_, kept)| *kept).map(|(piece, _)| piece.clone()).collect())
+    }
+}
+
+impl Model for Unigram {
+    fn vocabulary(&self) -> &[String] {
+        &self.vocabulary
+    }
+
+    fn token_to_index(&self) -> &HashMap<String, usize> {
+        &self.token_to_index
+    }
+
+    fn config(&self) -> &TokenConfig {
+        &self.config
+    }
+
+    fn normalizer(&self) -> &NormalizerPipeline {
+        &self.normalizer
+    }
+
+    fn decoder(&self) -> &DecoderPipeline {
+        &self.decoder
+    }
+
+    fn special_token_mode(&self) -> SpecialTokenMode {
+        self.special_token_mode
+    }
+
+    fn set_special_token_mode(&mut self, mode: SpecialTokenMode) {
+        self.special_token_mode = mode;
+    }
+
+    // Without a pre-tokenizer words are runs of non-whitespace, and each whitespace character is a token
+    fn encode_text(&self, text: &str, ids: &mut Vec<usize>) {
+        if !self.pre_tokenizer.is_empty() {
+            for (_, piece) in self.pre_tokenizer.pre_tokenize(text) {
+                self.encode_word(piece, ids);
+            }
+            return;
+        }
+
+        let mut start = 0;
+        for (i, c) in text.char_indices() {
+            if c.is_whitespace() {
+                self.encode_word(&text[start..i], ids);
+                self.encode_word(&text[i..i + c.len_utf8()], ids);
+                start = i + c.len_utf8();
+            }
+        }
+        self.encode_word(&text[start..], ids);
+    }
+
+    fn build_index(&mut self) -> Result<()> {
+        if self.scores.len() != self.vocabulary.len() {
+            return Err(TokenizerError::InvalidConfig(format!(
+                "Expected {} scores, one per token, got {}", self.vocabulary.len(), self.scores.len()
+            )));
+        }
+        self.token_to_index = index_tokens(&self.vocabulary);
+        self.config.resolve_indices(&self.token_to_index)?;
+
+        // Control, added, unknown and byte tokens are never found in text
+        let mut vocabulary_trie = TrieNode::new();
+        let mut lowest = f64::INFINITY;
+        for (i, token) in self.vocabulary.iter().enumerate() {
+            let hidden = self.config.is_control_token(i) || self.config.is_added_token(i)
+                || i == self.config.unknown.index || parse_byte_token(token).is_some();
+            if !hidden {
+                vocabulary_trie.insert(token);
+                lowest = lowest.min(self.scores[i]);
+            }
+        }
+        self.vocabulary_trie = vocabulary_trie;
+        self.unknown_score = if lowest.is_finite() { lowest } else { 0.0 } - UNKNOWN_PENALTY;
+        Ok(())
+    }
+}
+
+// Every substring of up to MAX_PIECE_CHARS characters seen at least twice, most frequent times
+// longest first, and every character of the corpus. Scores are log relative frequencies
+fn seed_pieces(words: &[(&str, f64)], size: usize) -> Vec<(String, f64)> {
+    let mut substrings: HashMap<&str, f64> = HashMap::new();
+    for &(word, frequency) in words {
+        let bounds: Vec<usize> = word.char_indices().map(|(i, _)| i).chain([word.len()]).collect();
+        for i in 0..bounds.len() - 1 {
+            for j in i + 1..bounds.len().min(i + MAX_PIECE_CHARS + 1) {
+                *substrings.entry(&word[bounds[i]..bounds[j]]).or_default() += frequency;
+            }
+        }
+    }
+
+    let (characters, mut longer): (Vec<_>, Vec<_>) = substrings.into_iter()
+        .filter(|&(piece, frequency)| frequency >= 2.0 || piece.chars().count() == 1)
+        .partition(|(piece, _)| piece.chars().count() == 1);
+    let weight = |(piece, frequency): &(&str, f64)| frequency * piece.chars().count() as f64;
+    longer.sort_by(|a, b| weight(b).total_cmp(&weight(a)).then_with(|| a.0.cmp(b.0)));
+    longer.truncate(size);
+
+    let mut pieces: Vec<(&str, f64)> = characters.into_iter().chain(longer).collect();
+    pieces.sort_by(|a, b| a.0.cmp(b.0));
+    let total: f64 = pieces.iter().map(|(_, frequency)| frequency).sum();
+    pieces.into_iter().map(|(piece, frequency)| (piece.to_string(), frequency.ln() - total.ln())).collect()
+}
+
+fn log_add(a: f64, b: f64) -> f64 {
+    if a == f64::NEG_INFINITY {
+        return b;
+    }
+    if b == f64::NEG_INFINITY {
+        return a;
+    }
+    a.max(b) + (-(a - b).abs()).exp().ln_1p()
+}
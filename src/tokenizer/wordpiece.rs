@@ -0,0 +1,242 @@
+use std::collections::{HashMap, HashSet};
+use serde::{Serialize, Deserialize};
+
+use crate::tokenizer::{Tokenizer, TokenConfig, TrainOptions, TrieNode};
+use crate::tokenizer::{EncodingMode, SpecialTokenMode};
+use crate::tokenizer::{Normalizer, NormalizerPipeline};
+use crate::tokenizer::{PreTokenizer, PreTokenizerPipeline, PreTokenizerStep};
+use crate::tokenizer::{DecoderPipeline, DecoderStep};
+use crate::tokenizer::model::{index_tokens, Model};
+use crate::tokenizer::error::Result;
+
+
+// WordPiece, as in BERT - each word is split greedily into the longest tokens in the vocabulary,
+// with every token after the first marked by a continuation prefix
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WordPiece {
+    // Tokens that can start a word
+    #[serde(skip_serializing, skip_deserializing)]
+    pub vocabulary_trie: TrieNode,
+    // Continuation tokens, without their prefix
+    #[serde(skip_serializing, skip_deserializing)]
+    pub continuation_trie: TrieNode,
+    // Ordered vocabulary - the index of each token is its position in this list
+    pub vocabulary: Vec<String>,
+    #[serde(default = "default_prefix")]
+    pub continuing_subword_prefix: String,
+    // Longer words are encoded as a single unknown token
+    #[serde(default = "default_max_input_chars_per_word")]
+    pub max_input_chars_per_word: usize,
+    #[serde(default)]
+    pub normalizer: NormalizerPipeline,
+    // Splits normalized text into pieces before they are split into words at whitespace
+    #[serde(default)]
+    pub pre_tokenizer: PreTokenizerPipeline,
+    #[serde(default = "default_decoder")]
+    pub decoder: DecoderPipeline,
+    #[serde(default)]
+    pub special_token_mode: SpecialTokenMode,
+    #[serde(skip_serializing, skip_deserializing)]
+    pub token_to_index: HashMap<String, usize>,
+    pub config: TokenConfig,
+}
+
+fn default_prefix() -> String {
+    "##".to_string()
+}
+
+fn default_max_input_chars_per_word() -> usize {
+    100
+}
+
+fn default_decoder() -> DecoderPipeline {
+    DecoderPipeline::new(vec![DecoderStep::WordPiece { prefix: default_prefix() }, DecoderStep::CleanUp])
+}
+
+impl WordPiece {
+    // Fails if a special token of `config` is missing from the vocabulary
+    pub fn new(vocabulary: Vec<String>, config: TokenConfig) -> Result<Self> {
+        let mut wordpiece = WordPiece {
+            vocabulary_trie: TrieNode::new(),
+            continuation_trie: TrieNode::new(),
+            vocabulary,
+            continuing_subword_prefix: default_prefix(),
+            max_input_chars_per_word: default_max_input_chars_per_word(),
+            normalizer: NormalizerPipeline::default(),
+            pre_tokenizer: PreTokenizerPipeline::default(),
+            decoder: default_decoder(),
+            special_token_mode: SpecialTokenMode::default(),
+            token_to_index: HashMap::new(),
+            config,
+        };
+        wordpiece.build_index()?;
+        Ok(wordpiece)
+    }
+
+    pub fn set_decoder(&mut self, decoder: DecoderPipeline) {
+        self.decoder = decoder;
+    }
+
+    // Words are the pre-tokenized pieces of normalized text split at whitespace
+    fn words<'a>(pre_tokenizer: &PreTokenizerPipeline, text: &'a str) -> Vec<&'a str> {
+        if pre_tokenizer.is_empty() {
+            return text.split_whitespace().collect();
+        }
+        pre_tokenizer.pre_tokenize(text).into_iter().flat_map(|(_, piece)| piece.split_whitespace()).collect()
+    }
+
+    // Greedy longest-match-first - a word with any part missing from the vocabulary is unknown
+    fn encode_word(&self, word: &str, ids: &mut Vec<usize>) {
+        if word.chars().count() > self.max_input_chars_per_word {
+            ids.push(self.config.unknown.index);
+            return;
+        }
+
+        let mut pieces = Vec::new();
+        let mut start = 0;
+        while start < word.len() {
+            let trie = if start == 0 { &self.vocabulary_trie } else { &self.continuation_trie };
+            let Some((length, piece)) = trie.find_longest_prefix(&word[start..]) else {
+                ids.push(self.config.unknown.index);
+                return;
+            };
+            let token = if start == 0 { piece.to_string() } else { format!("{}{}", self.continuing_subword_prefix, piece) };
+            pieces.push(self.token_to_index[&token]);
+            start += length;
+        }
+        ids.extend(pieces);
+    }
+
+    // Run `iterations` of BPE training on the words of the corpus, then keep each learned token in
+    // the forms it took while merging - at the start of a word, prefixed as a continuation, or
+    // both. Every character is kept in both forms
+    pub fn train(source: &str, iterations: usize, output_filepath: &str, options: TrainOptions) -> Result<Self> {
+        let config = TokenConfig::new();
+        let source = options.normalizer.normalize(source);
+        let words = Self::words(&options.pre_tokenizer, &source);
+
+        // Words are already normalized, and a piece per word keeps merges from crossing them
+        let bpe_options = TrainOptions {
+            byte_fallback: false,
+            normalizer: NormalizerPipeline::cased(),
+            pre_tokenizer: PreTokenizerPipeline::new(vec![PreTokenizerStep::regex(r"\S+")?]),
+            post_processor: None,
+        };
+        let mut bpe = Tokenizer::train_cpu_unsaved(&words.join(" "), iterations, bpe_options)?;
+        bpe.set_mode(EncodingMode::Merge);
+        bpe.set_special_token_mode(SpecialTokenMode::Plain);
+
+        let mut initial = vec![false; bpe.vocab_size()];
+        let mut continuation = vec![false; bpe.vocab_size()];
+        let special = bpe.config.get_indices();
+        for (index, token) in bpe.vocabulary.iter().enumerate() {
+            if token.chars().count() == 1 && !special.contains(&index) {
+                initial[index] = true;
+                continuation[index] = true;
+            }
+        }
+        // The parts each token was first merged from
+        let mut parts = HashMap::new();
+        for (first, second) in bpe.merge_rules.iter() {
+            if let (Some(a), Some(b), Some(merged)) = (bpe.get_index(first), bpe.get_index(second), bpe.get_index(&format!("{}{}", first, second))) {
+                parts.entry(merged).or_insert((a, b));
+            }
+        }
+
+        // A token at the start of a word was merged from a token at the start and a continuation,
+        // so intermediate tokens are kept in the forms they had during training
+        let mut distinct = words.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        let mut stack = Vec::new();
+        for word in distinct {
+            stack.extend(bpe.tokenize(word).into_iter().enumerate().map(|(i, index)| (index, i == 0)));
+            while let Some((index, at_start)) = stack.pop() {
+                let form = if at_start { &mut initial } else { &mut continuation };
+                if std::mem::replace(&mut form[index], true) {
+                    continue;
+                }
+                if let Some(&(first, second)) = parts.get(&index) {
+                    stack.push((first, at_start));
+                    stack.push((second, false));
+                }
+            }
+        }
+
+        // Special tokens first, then tokens in the order BPE learned them
+        let prefix = default_prefix();
+        let mut vocabulary = config.get_values();
+        let mut seen: HashSet<String> = vocabulary.iter().cloned().collect();
+        for (index, token) in bpe.vocabulary.iter().enumerate() {
+            if initial[index] && seen.insert(token.clone()) {
+                vocabulary.push(token.clone());
+            }
+            if continuation[index] {
+                vocabulary.push(format!("{}{}", prefix, token));
+            }
+        }
+
+        let mut wordpiece = WordPiece::new(vocabulary, config)?;
+        wordpiece.normalizer = options.normalizer.clone();
+        wordpiece.pre_tokenizer = options.pre_tokenizer.clone();
+        wordpiece.save(output_filepath)?;
+        Ok(wordpiece)
+    }
+}
+
+impl Model for WordPiece {
+    fn vocabulary(&self) -> &[String] {
+        &self.vocabulary
+    }
+
+    fn token_to_index(&self) -> &HashMap<String, usize> {
+        &self.token_to_index
+    }
+
+    fn config(&self) -> &TokenConfig {
+        &self.config
+    }
+
+    fn normalizer(&self) -> &NormalizerPipeline {
+        &self.normalizer
+    }
+
+    fn decoder(&self) -> &DecoderPipeline {
+        &self.decoder
+    }
+
+    fn special_token_mode(&self) -> SpecialTokenMode {
+        self.special_token_mode
+    }
+
+    fn set_special_token_mode(&mut self, mode: SpecialTokenMode) {
+        self.special_token_mode = mode;
+    }
+
+    fn encode_text(&self, text: &str, ids: &mut Vec<usize>) {
+        for word in Self::words(&self.pre_tokenizer, text) {
+            self.encode_word(word, ids);
+        }
+    }
+
+    fn build_index(&mut self) -> Result<()> {
+        self.token_to_index = index_tokens(&self.vocabulary);
+        self.config.resolve_indices(&self.token_to_index)?;
+
+        // Control, added and unknown tokens are never found in text
+        let mut vocabulary_trie = TrieNode::new();
+        let mut continuation_trie = TrieNode::new();
+        for (i, token) in self.vocabulary.iter().enumerate() {
+            if self.config.is_control_token(i) || self.config.is_added_token(i) || i == self.config.unknown.index {
+                continue;
+            }
+            match token.strip_prefix(self.continuing_subword_prefix.as_str()) {
+                Some(piece) if !piece.is_empty() => continuation_trie.insert(piece),
+                _ => vocabulary_trie.insert(token),
+            }
+        }
+        self.vocabulary_trie = vocabulary_trie;
+        self.continuation_trie = continuation_trie;
+        Ok(())
+    }
+}
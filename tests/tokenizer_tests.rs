@@ -1,25 +1,36 @@
-use rust_bpe::tokenizer::Tokenizer;
+use std::collections::HashMap;
+
+use rust_bpe::tokenizer::{bytes, Encoding, EncodingMode, Model, Tokenizer, TokenizerError, TrainOptions, TrieNode, Unigram, WordPiece};
+use rust_bpe::tokenizer::{BatchOptions, Padding, PaddingSide, Truncation, TruncationStrategy};
+use rust_bpe::tokenizer::{AddedToken, DecodeStream, PostProcessor, SpecialTokenMode};
+use rust_bpe::tokenizer::{Decoder, DecoderPipeline, DecoderStep};
+use rust_bpe::tokenizer::{Normalizer, NormalizerPipeline, NormalizerStep};
+use rust_bpe::tokenizer::{PreTokenizer, PreTokenizerPipeline, PreTokenizerStep};
+use rust_bpe::tokenizer::normalizer::Pattern;
+use rust_bpe::tokenizer::pre_tokenizer::{CL100K_PATTERN, GPT2_PATTERN};
 
 
 #[test]
+#[ignore = "requires the local training corpus"]
 fn train_tokenizer() {
-    let source = Tokenizer::process_dataset("F:/datasets/tokenizer/train");
+    let source = Tokenizer::process_dataset("F:/datasets/tokenizer/train").unwrap();
     let iterations = 25000;
     let output = "./src/models/train.json";
     let pretrained_model = None;
-    let tokenizer = Tokenizer::train_cpu(&source, iterations, output, pretrained_model);
+    let _tokenizer = Tokenizer::train_cpu(&source, iterations, output, pretrained_model).unwrap();
 }
 
 #[test]
+#[ignore = "requires the local validation corpus"]
 fn validate_tokenizer() {
-    let text = Tokenizer::process_dataset("F:/datasets/tokenizer/validate/");
+    let text = Tokenizer::process_dataset("F:/datasets/tokenizer/validate/").unwrap();
     let tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
 
     let time = std::time::Instant::now();
     let tokens = tokenizer.tokenize(&text);
     println!("Tokenization time: {:?}", time.elapsed());
-    let token_vals = tokenizer.get_tokens(&tokens);
-    let token_indices = tokenizer.get_indices(&token_vals);
+    let token_vals = tokenizer.get_tokens(&tokens).unwrap();
+    let token_indices = tokenizer.get_indices(&token_vals).unwrap();
     let detokenized = tokenizer.detokenize(&tokens);
 
     println!("Input: {:?}", &text);
@@ -27,9 +38,1110 @@ fn validate_tokenizer() {
     println!("Detokenized: {:?}", detokenized);
     println!("Num tokens: {:?}", tokens.len());
     println!("Vocabulary size: {:?}", tokenizer.vocabulary.len());
+    assert_eq!(token_indices, tokens);
 
-    let expected = Tokenizer::clean_text(&text);
+    let expected = tokenizer.normalize(&text);
     let actual = detokenized;
     assert_eq!(actual.trim(), expected.trim());
 }
 
+#[test]
+fn indices_are_stable_across_loads() {
+    let first = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let second = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let rebuilt = Tokenizer::new(first.get_vocabulary(), first.get_merge_rules(), first.config.clone()).unwrap();
+
+    let text = "the quick brown fox jumps over the lazy dog";
+    assert_eq!(first.tokenize(text), second.tokenize(text));
+    assert_eq!(first.tokenize(text), rebuilt.tokenize(text));
+    for (i, token) in first.vocabulary.iter().enumerate() {
+        assert_eq!(first.get_index(token), Some(i));
+        assert_eq!(second.get_token(i).as_ref(), Some(token));
+    }
+}
+
+#[test]
+fn trained_indices_follow_merge_order() {
+    let output = std::env::temp_dir().join("rust_bpe_merge_order.json");
+    let output = output.to_str().unwrap();
+    let source = "low lower lowest newer newest wider widest";
+    let tokenizer = Tokenizer::train_cpu(source, 10, output, None).unwrap();
+    let loaded = Tokenizer::load(output).unwrap();
+
+    // Special tokens come first, followed by characters and then merges in order
+    assert_eq!(&tokenizer.vocabulary[..9], &tokenizer.config.get_values()[..]);
+    assert_eq!(tokenizer.config.get_indices(), (0..9).collect::<Vec<_>>());
+    for (first, second) in tokenizer.merge_rules.iter() {
+        let merged = tokenizer.get_index(&format!("{}{}", first, second)).unwrap();
+        assert!(merged > tokenizer.get_index(first).unwrap());
+        assert!(merged > tokenizer.get_index(second).unwrap());
+    }
+    assert_eq!(tokenizer.vocabulary, loaded.vocabulary);
+    assert_eq!(tokenizer.tokenize(source), loaded.tokenize(source));
+}
+
+// Reference encoder applying each merge rule across the whole sequence, as train_cpu does
+fn apply_merges_naively(tokenizer: &Tokenizer, word: &str) -> Vec<usize> {
+    let mut tokens: Vec<String> = word.chars().map(|c| c.to_string()).collect();
+    for (first, second) in tokenizer.merge_rules.iter() {
+        let mut merged = Vec::with_capacity(tokens.len());
+        let mut i = 0;
+        while i < tokens.len() {
+            if i + 1 < tokens.len() && &tokens[i] == first && &tokens[i + 1] == second {
+                merged.push(format!("{}{}", first, second));
+                i += 2;
+            } else {
+                merged.push(tokens[i].clone());
+                i += 1;
+            }
+        }
+        tokens = merged;
+    }
+    tokenizer.get_indices(&tokens).unwrap()
+}
+
+#[test]
+fn merge_mode_matches_training_merges() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.set_mode(EncodingMode::Merge);
+
+    for text in ["the quick brown fox jumps over the lazy dog", "tokenization, unbelievably!", "aaaaaaa"] {
+        assert_eq!(tokenizer.tokenize(text), apply_merges_naively(&tokenizer, text));
+    }
+    let tokens = tokenizer.tokenize("first line\nsecond line");
+    assert_eq!(tokenizer.detokenize(&tokens), "first line\nsecond line");
+    assert!(tokens.contains(&tokenizer.config.newline.index));
+}
+
+#[test]
+fn byte_fallback_round_trips_unseen_characters() {
+    let output = std::env::temp_dir().join("rust_bpe_byte_fallback.json");
+    let options = TrainOptions { byte_fallback: true, ..Default::default() };
+    let source = "low lower lowest newer newest wider widest";
+    let mut tokenizer = Tokenizer::train_cpu_with_options(source, 10, output.to_str().unwrap(), None, options).unwrap();
+    assert_eq!(&tokenizer.vocabulary[9..265], &bytes::byte_tokens()[..]);
+
+    let text = "lower 日本語 🦀 \u{7}end";
+    for mode in [EncodingMode::Greedy, EncodingMode::Merge] {
+        tokenizer.set_mode(mode);
+        let tokens = tokenizer.tokenize(text);
+        assert!(!tokens.contains(&tokenizer.config.unknown.index));
+        assert_eq!(tokenizer.detokenize(&tokens), text);
+    }
+
+    let loaded = Tokenizer::load(output.to_str().unwrap()).unwrap();
+    assert!(loaded.byte_fallback);
+    assert_eq!(loaded.detokenize(&loaded.tokenize(text)), text);
+
+    // Text spelling a byte token is encoded as text, never as the byte it names
+    let text = "lower <0x41> <0x80><0x20>";
+    for mode in [EncodingMode::Greedy, EncodingMode::Merge] {
+        tokenizer.set_mode(mode);
+        let tokens = tokenizer.tokenize(text);
+        assert!(!tokens.contains(&tokenizer.get_index("<0x41>").unwrap()));
+        assert_eq!(tokenizer.detokenize(&tokens), text);
+    }
+}
+
+#[test]
+fn byte_fallback_keeps_existing_indices() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let vocab_size = tokenizer.vocab_size();
+    let text = "rare \u{1d11e} symbols";
+    let before = tokenizer.tokenize(text);
+    assert!(before.contains(&tokenizer.config.unknown.index));
+
+    tokenizer.enable_byte_fallback().unwrap();
+    tokenizer.set_mode(EncodingMode::Merge);
+    let after = tokenizer.tokenize(text);
+    assert_eq!(tokenizer.get_index("<0x00>"), Some(vocab_size));
+    assert!(!after.contains(&tokenizer.config.unknown.index));
+    assert_eq!(tokenizer.detokenize(&after), text);
+}
+
+#[test]
+fn trie_prefix_lengths_are_in_bytes() {
+    let mut trie = TrieNode::new();
+    trie.insert("é");
+    trie.insert("éa");
+    trie.insert("日本");
+    assert_eq!(trie.find_longest_prefix("éab"), Some((3, "éa")));
+    assert_eq!(trie.find_longest_prefix("日本語"), Some((6, "日本")));
+    assert_eq!(trie.find_longest_prefix("日"), None);
+}
+
+#[test]
+fn tokenize_handles_multibyte_text() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.enable_byte_fallback().unwrap();
+    let text = "café déjà vu, naïve résumé 🥬 東京 ünïcödé";
+    for mode in [EncodingMode::Greedy, EncodingMode::Merge] {
+        tokenizer.set_mode(mode);
+        let tokens = tokenizer.tokenize(text);
+        assert!(tokenizer.get_tokens(&tokens).unwrap().iter().any(|token| token.contains('é')));
+        assert_eq!(tokenizer.detokenize(&tokens), text);
+    }
+}
+
+#[test]
+fn cased_mode_preserves_capitalization() {
+    let output = std::env::temp_dir().join("rust_bpe_cased.json");
+    let options = TrainOptions { normalizer: NormalizerPipeline::cased(), ..Default::default() };
+    let source = "Apple apple APPLE Apple pie apple pie";
+    let tokenizer = Tokenizer::train_cpu_with_options(source, 20, output.to_str().unwrap(), None, options).unwrap();
+    assert!(tokenizer.get_index("A").is_some() && tokenizer.get_index("a").is_some());
+
+    let loaded = Tokenizer::load(output.to_str().unwrap()).unwrap();
+    assert!(loaded.is_cased());
+    let text = "Apple pie and apple PIE";
+    assert_eq!(loaded.normalize(text), text);
+    assert_ne!(loaded.tokenize("Apple"), loaded.tokenize("apple"));
+    assert_eq!(loaded.detokenize(&loaded.tokenize("Apple pie apple")), "Apple pie apple");
+
+    let uncased = Tokenizer::train_cpu(source, 20, output.to_str().unwrap(), None).unwrap();
+    assert!(!uncased.is_cased() && uncased.get_index("A").is_none());
+    assert_eq!(uncased.tokenize("Apple"), uncased.tokenize("apple"));
+}
+
+#[test]
+fn legacy_cased_flag_maps_to_a_normalizer() {
+    let tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let mut json: serde_json::Value = serde_json::from_str(&serde_json::to_string(&tokenizer).unwrap()).unwrap();
+    json.as_object_mut().unwrap().remove("normalizer");
+
+    // Files saved with the `cased` flag of earlier versions keep their casing behaviour
+    json["cased"] = true.into();
+    let cased = Tokenizer::from_json(&json.to_string()).unwrap();
+    assert_eq!(cased.normalizer, NormalizerPipeline::cased());
+    assert_eq!(cased.normalize("Apple"), "Apple");
+    assert!(!serde_json::to_string(&cased).unwrap().contains("\"cased\""));
+
+    json["cased"] = false.into();
+    let uncased = Tokenizer::from_json(&json.to_string()).unwrap();
+    assert_eq!(uncased.normalizer, NormalizerPipeline::uncased());
+    assert_eq!(uncased.normalize("Apple"), "apple");
+}
+
+#[test]
+fn normalizer_pipeline_is_saved_and_applied() {
+    let pipeline: NormalizerPipeline = serde_json::from_str(r#"[
+        {"type": "Nfkc"},
+        {"type": "StripControl"},
+        {"type": "StripAccents"},
+        {"type": "Lowercase"},
+        {"type": "Replace", "pattern": "[0-9]+", "content": "0"},
+        {"type": "CollapseWhitespace"}
+    ]"#).unwrap();
+    assert_eq!(pipeline.normalize("Ｃafé\u{7}  Déjà\t 2024 \n\n x"), "cafe deja 0\nx");
+    assert_eq!(NormalizerStep::Nfd.normalize("é").chars().count(), 2);
+
+    // Catastrophic backtracking stops the replacement, and the unsearched text is kept as is
+    let runaway = format!("x y {}c z", "a".repeat(40));
+    let replace = NormalizerStep::Replace { pattern: Pattern::new(r"\s|(?:a+)+(?=b)").unwrap(), content: "_".into() };
+    assert_eq!(replace.normalize(&runaway), format!("x_y_{}", &runaway[4..]));
+
+    let output = std::env::temp_dir().join("rust_bpe_normalizer.json");
+    let options = TrainOptions { normalizer: pipeline.clone(), ..Default::default() };
+    let tokenizer = Tokenizer::train_cpu_with_options("Café crème 123 CAFE", 5, output.to_str().unwrap(), None, options).unwrap();
+    assert!(tokenizer.get_index("é").is_none() && tokenizer.get_index("1").is_none());
+
+    let loaded = Tokenizer::load(output.to_str().unwrap()).unwrap();
+    assert_eq!(loaded.normalizer, pipeline);
+    assert_eq!(loaded.tokenize("CAFÉ 99"), loaded.tokenize("cafe 0"));
+}
+
+#[test]
+fn pre_tokenizer_steps_cover_the_input() {
+    let text = "Hello, world! It's 2024...  ok";
+    let pieces = |pipeline: PreTokenizerPipeline| -> Vec<&str> {
+        pipeline.pre_tokenize(text).into_iter().map(|(_, piece)| piece).collect()
+    };
+
+    assert_eq!(pieces(PreTokenizerPipeline::gpt2()),
+        vec!["Hello", ",", " world", "!", " It", "'s", " 2024", "...", " ", " ok"]);
+    assert_eq!(pieces(PreTokenizerPipeline::cl100k()),
+        vec!["Hello", ",", " world", "!", " It", "'s", " ", "202", "4", "...", " ", " ok"]);
+    assert_eq!(pieces(PreTokenizerPipeline::new(vec![
+        PreTokenizerStep::Whitespace,
+        PreTokenizerStep::Punctuation,
+        PreTokenizerStep::Digits { individual: true },
+    ])), vec!["Hello", ",", " world", "!", " It", "'", "s", " ", "2", "0", "2", "4", ".", ".", ".", "  ok"]);
+
+    for (offset, piece) in PreTokenizerPipeline::gpt2().pre_tokenize(text) {
+        assert_eq!(&text[offset..offset + piece.len()], piece);
+    }
+
+    // Catastrophic backtracking stops the regex, and the unsearched text is kept whole
+    let runaway = format!("x y {}c z", "a".repeat(40));
+    let pipeline = PreTokenizerPipeline::new(vec![PreTokenizerStep::regex(r"\s|(?:a+)+(?=b)").unwrap()]);
+    let pieces: Vec<&str> = pipeline.pre_tokenize(&runaway).into_iter().map(|(_, piece)| piece).collect();
+    assert_eq!(pieces, vec!["x", " ", "y", " ", &runaway[4..]]);
+}
+
+#[test]
+fn pre_tokenizer_bounds_training_and_encoding() {
+    let output = std::env::temp_dir().join("rust_bpe_pre_tokenizer.json");
+    let options = TrainOptions { pre_tokenizer: PreTokenizerPipeline::gpt2(), ..Default::default() };
+    let source = "end. end. end. the end. the end. 123 123 123";
+    let mut tokenizer = Tokenizer::train_cpu_with_options(source, 30, output.to_str().unwrap(), None, options).unwrap();
+
+    // Merges never join letters with punctuation or digits with letters
+    for (first, second) in tokenizer.merge_rules.iter() {
+        let merged = format!("{}{}", first, second);
+        assert_eq!(PreTokenizerPipeline::gpt2().pre_tokenize(&merged).len(), 1, "{:?}", merged);
+    }
+
+    let loaded = Tokenizer::load(output.to_str().unwrap()).unwrap();
+    assert_eq!(loaded.pre_tokenizer, PreTokenizerPipeline::gpt2());
+    for mode in [EncodingMode::Greedy, EncodingMode::Merge] {
+        tokenizer.set_mode(mode);
+        let tokens = tokenizer.get_tokens(&tokenizer.tokenize("the end. 123")).unwrap();
+        assert!(tokens.iter().all(|token| !token.contains('.') || token.len() == 1), "{:?}", tokens);
+        assert_eq!(tokens.concat(), "the end. 123");
+    }
+}
+
+fn assert_offsets_match(encoding: &Encoding, text: &str) {
+    let chars: Vec<char> = text.chars().collect();
+    for (i, &(start, end)) in encoding.offsets.iter().enumerate() {
+        let (char_start, char_end) = encoding.char_offsets[i];
+        let by_chars: String = chars[char_start..char_end].iter().collect();
+        assert_eq!(&text[start..end], by_chars);
+        if bytes::parse_byte_token(&encoding.tokens[i]).is_some() {
+            // Byte tokens report the character they are part of
+            assert_eq!(by_chars.chars().count(), 1);
+        } else {
+            assert_eq!(by_chars, encoding.tokens[i]);
+        }
+    }
+}
+
+#[test]
+fn encode_reports_offsets_and_masks() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.enable_byte_fallback().unwrap();
+    tokenizer.set_special_token_mode(SpecialTokenMode::Match);
+    let text = "naïve café <|eot|> 🦀 works";
+    let encoding = tokenizer.encode(text);
+
+    assert_eq!(encoding.ids, tokenizer.tokenize(text));
+    assert_eq!(encoding.attention_mask, vec![1; encoding.len()]);
+    assert_offsets_match(&encoding, text);
+    let eot = encoding.ids.iter().position(|&id| id == tokenizer.config.eot.index).unwrap();
+    assert_eq!(encoding.special_tokens_mask.iter().filter(|&&m| m == 1).count(), 1);
+    assert_eq!(encoding.special_tokens_mask[eot], 1);
+
+    let words: Vec<Option<usize>> = encoding.word_ids.clone();
+    assert_eq!(words.first(), Some(&Some(0)));
+    // The inline <|eot|> is a special token rather than a word
+    assert_eq!(words.iter().flatten().max(), Some(&3));
+
+    tokenizer.set_pre_tokenizer(PreTokenizerPipeline::gpt2());
+    tokenizer.set_mode(EncodingMode::Merge);
+    let encoding = tokenizer.encode("hello world, again");
+    assert_offsets_match(&encoding, "hello world, again");
+    let last = *encoding.word_ids.last().unwrap();
+    assert_eq!(last, Some(3));
+}
+
+#[test]
+fn normalized_offsets_map_to_original_text() {
+    let pipeline: NormalizerPipeline = serde_json::from_str(r#"[
+        {"type": "Nfkc"},
+        {"type": "Lowercase"},
+        {"type": "CollapseWhitespace"},
+        {"type": "Replace", "pattern": "ﬁ|fi", "content": "f-i"}
+    ]"#).unwrap();
+    let text = "Ｆｏｏ \t BAR ﬁx İs";
+    let normalized = pipeline.normalize_aligned(text);
+    assert_eq!(normalized.normalized, pipeline.normalize(text));
+    let normalizer: &dyn Normalizer = &pipeline;
+    assert_eq!(normalizer.normalize_aligned(text), normalized);
+    assert_eq!(normalized.normalized, "foo bar f-ix i\u{307}s");
+
+    let find = |needle: &str| {
+        let start = normalized.normalized.find(needle).unwrap();
+        let (start, end) = normalized.original_range(start, start + needle.len());
+        &text[start..end]
+    };
+    assert_eq!(find("foo"), "Ｆｏｏ");
+    assert_eq!(find(" "), " \t ");
+    assert_eq!(find("bar"), "BAR");
+    assert_eq!(find("f-i"), "ﬁ");
+    assert_eq!(find("x"), "x");
+    assert_eq!(find("i\u{307}"), "İ");
+    assert_eq!(find("s"), "s");
+}
+
+#[test]
+fn encode_offsets_point_into_original_input() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.enable_byte_fallback().unwrap();
+    let text = "İSTANBUL and ΣΟΦΟΣ Were HERE";
+    let encoding = tokenizer.encode(text);
+
+    let mut last_start = 0;
+    for (i, &(start, end)) in encoding.offsets.iter().enumerate() {
+        assert!(start >= last_start && end <= text.len());
+        assert!(text.is_char_boundary(start) && text.is_char_boundary(end));
+        let original = &text[start..end];
+        if original.is_ascii() {
+            assert_eq!(original.to_lowercase(), encoding.tokens[i]);
+        }
+        last_start = start;
+    }
+    let were = encoding.tokens.iter().position(|token| token.contains("were")).unwrap();
+    let (start, end) = encoding.offsets[were];
+    assert!(text[start..end].contains("Were"));
+    let (char_start, _) = encoding.char_offsets[were];
+    assert_eq!(text.chars().skip(char_start).collect::<String>(), text[start..].to_string());
+}
+
+#[test]
+fn encode_batch_pads_and_truncates() {
+    let tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let texts = ["short", "a somewhat longer sentence to encode", ""];
+    let pad = tokenizer.config.pad.index;
+    let lengths: Vec<usize> = texts.iter().map(|text| tokenizer.tokenize(text).len()).collect();
+    let longest = *lengths.iter().max().unwrap();
+
+    let batch = tokenizer.encode_batch(&texts, &BatchOptions::default()).unwrap();
+    assert_eq!(batch.ids.len(), texts.len());
+    for (i, row) in batch.ids.iter().enumerate() {
+        assert_eq!(row.len(), longest);
+        assert_eq!(&row[..lengths[i]], &tokenizer.tokenize(texts[i])[..]);
+        assert!(row[lengths[i]..].iter().all(|&id| id == pad));
+        let attended = batch.attention_mask[i].iter().filter(|&&m| m == 1).count();
+        assert_eq!(attended, lengths[i]);
+    }
+
+    let options = BatchOptions { padding: Padding::Fixed(4), padding_side: PaddingSide::Left, max_length: None };
+    let batch = tokenizer.encode_batch(&texts, &options).unwrap();
+    assert!(batch.ids.iter().all(|row| row.len() == 4));
+    assert_eq!(batch.ids[2], vec![pad; 4]);
+    assert_eq!(batch.attention_mask[0], [vec![0; 4 - lengths[0]], vec![1; lengths[0]]].concat());
+    assert_eq!(batch.ids[1], tokenizer.tokenize(texts[1])[..4]);
+
+    let options = BatchOptions { max_length: Some(2), ..BatchOptions::default() };
+    let batch = tokenizer.encode_batch(&texts, &options).unwrap();
+    assert!(batch.ids.iter().all(|row| row.len() == 2));
+    assert!(batch.encodings.iter().all(|encoding| encoding.offsets.len() == 2));
+
+    // Texts are cut before the template is applied, so the closing token survives truncation
+    let mut tokenizer = tokenizer;
+    let config = tokenizer.config.clone();
+    let template = format!("{} $A {}", config.sot.value, config.eot.value);
+    tokenizer.set_post_processor(Some(PostProcessor::new(&template, "$A $B").unwrap())).unwrap();
+    let options = BatchOptions { max_length: Some(4), ..BatchOptions::default() };
+    let batch = tokenizer.encode_batch(&texts, &options).unwrap();
+    assert_eq!(batch.ids[1], [vec![config.sot.index], tokenizer.tokenize(texts[1])[..2].to_vec(), vec![config.eot.index]].concat());
+    assert!(batch.encodings.iter().all(|encoding| encoding.overflowing.is_empty()));
+    for (row, encoding) in batch.ids.iter().zip(batch.encodings.iter()) {
+        let attended = encoding.attention_mask.iter().filter(|&&m| m == 1).count();
+        assert_eq!(row[attended - 1], config.eot.index);
+    }
+    let options = BatchOptions { max_length: Some(2), ..BatchOptions::default() };
+    assert!(tokenizer.encode_batch(&texts, &options).is_err());
+}
+
+#[test]
+fn truncation_windows_overlap_and_keep_boundary_tokens() {
+    let tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let (sot, eot) = (tokenizer.config.sot.index, tokenizer.config.eot.index);
+    let text = "the quick brown fox jumps over the lazy dog and keeps running far away";
+    let ids = tokenizer.tokenize(text);
+
+    let truncation = Truncation { max_length: 6, strategy: TruncationStrategy::LongestFirst, stride: 1 };
+    let encoding = tokenizer.encode_truncated(text, None, &truncation).unwrap();
+    let windows: Vec<&Encoding> = std::iter::once(&encoding).chain(encoding.overflowing.iter()).collect();
+    assert!(windows.len() > 1);
+    let mut covered = Vec::new();
+    for (i, window) in windows.iter().enumerate() {
+        assert!(window.len() <= 6);
+        assert_eq!(window.ids.first(), Some(&sot));
+        assert_eq!(window.ids.last(), Some(&eot));
+        let content = &window.ids[1..window.len() - 1];
+        // Each window repeats the last token of the previous one
+        if i > 0 {
+            assert_eq!(content[0], *covered.last().unwrap());
+            covered.extend_from_slice(&content[1..]);
+        } else {
+            covered.extend_from_slice(content);
+        }
+    }
+    assert_eq!(covered, ids);
+
+    let pair = "short pair";
+    let pair_ids = tokenizer.tokenize(pair);
+    let truncation = Truncation { max_length: 3 + pair_ids.len() + 2, strategy: TruncationStrategy::OnlyFirst, stride: 0 };
+    let encoding = tokenizer.encode_truncated(text, Some(pair), &truncation).unwrap();
+    assert_eq!(encoding.ids, [vec![sot], ids[..2].to_vec(), vec![eot], pair_ids.clone(), vec![eot]].concat());
+    assert!(encoding.overflowing.iter().all(|window| window.ids.ends_with(&[&pair_ids[..], &[eot]].concat())));
+
+    let truncation = Truncation { max_length: 3 + 4, strategy: TruncationStrategy::LongestFirst, stride: 0 };
+    let encoding = tokenizer.encode_truncated(text, Some(pair), &truncation).unwrap();
+    assert_eq!(encoding.len(), 7);
+    assert_eq!(&encoding.ids[1..3], &ids[..2]);
+    assert_eq!(&encoding.ids[4..6], &pair_ids[..2.min(pair_ids.len())]);
+
+    // Windows of the pair are combined with every window of the first text
+    let truncation = Truncation { max_length: 3 + 3, strategy: TruncationStrategy::LongestFirst, stride: 0 };
+    let encoding = tokenizer.encode_truncated("one two three four", Some("five six seven eight"), &truncation).unwrap();
+    let first_windows = tokenizer.tokenize("one two three four").len().div_ceil(2);
+    let second_windows = tokenizer.tokenize("five six seven eight").len();
+    assert_eq!(encoding.overflowing.len() + 1, first_windows * second_windows);
+
+    // A budget with no room left for a text is an error rather than an over-long window
+    let truncation = Truncation { max_length: 2, strategy: TruncationStrategy::LongestFirst, stride: 0 };
+    assert!(matches!(tokenizer.encode_truncated(text, None, &truncation), Err(TokenizerError::InvalidConfig(_))));
+    let truncation = Truncation { max_length: 4, strategy: TruncationStrategy::OnlySecond, stride: 0 };
+    assert!(tokenizer.encode_truncated(text, Some(pair), &truncation).is_err());
+    assert!(tokenizer.encode_truncated("", None, &Truncation { max_length: 2, ..truncation }).is_ok());
+}
+
+#[test]
+fn post_processor_template_wraps_and_sets_type_ids() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let config = tokenizer.config.clone();
+    let (sot, eot, eos) = (config.sot.index, config.eot.index, config.eos.index);
+    let (first, second) = ("hello world", "goodbye");
+    let (first_ids, second_ids) = (tokenizer.tokenize(first), tokenizer.tokenize(second));
+
+    let single = format!("{} $A {}", config.sot.value, config.eot.value);
+    let pair = format!("{} $A {} $B:1 {}:1", config.sot.value, config.eos.value, config.eot.value);
+    let processor = PostProcessor::new(&single, &pair).unwrap();
+    assert!(tokenizer.set_post_processor(Some(PostProcessor::new("<missing> $A", "$A $B").unwrap())).is_err());
+    tokenizer.set_post_processor(Some(processor.clone())).unwrap();
+
+    let encoding = tokenizer.encode(first);
+    assert_eq!(encoding.ids, [vec![sot], first_ids.clone(), vec![eot]].concat());
+    assert_eq!(encoding.type_ids, vec![0; encoding.len()]);
+    assert_eq!(encoding.special_tokens_mask.first(), Some(&1));
+    assert_offsets_match(&encoding.slice(1, encoding.len() - 1), first);
+
+    let encoding = tokenizer.encode_pair(first, second);
+    assert_eq!(encoding.ids, [vec![sot], first_ids.clone(), vec![eos], second_ids.clone(), vec![eot]].concat());
+    let expected_types = [vec![0; first_ids.len() + 2], vec![1; second_ids.len() + 1]].concat();
+    assert_eq!(encoding.type_ids, expected_types);
+
+    let path = std::env::temp_dir().join("rust_bpe_post_processor_test.json");
+    tokenizer.save(path.to_str().unwrap()).unwrap();
+    let loaded = Tokenizer::load(path.to_str().unwrap()).unwrap();
+    assert_eq!(loaded.post_processor, Some(processor));
+    assert_eq!(loaded.encode_pair(first, second), encoding);
+    assert!(PostProcessor::new("$A $B", "$A $B").is_err());
+}
+
+#[test]
+fn inline_special_tokens_match_or_stay_plain() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.set_mode(EncodingMode::Merge);
+    tokenizer.set_special_token_mode(SpecialTokenMode::Match);
+    let config = tokenizer.config.clone();
+    let text = format!("Hello{}WORLD {} again", config.eot.value, config.mask.value);
+
+    let ids = tokenizer.tokenize(&text);
+    let expected = [
+        tokenizer.tokenize("Hello"), vec![config.eot.index],
+        tokenizer.tokenize("WORLD "), vec![config.mask.index], tokenizer.tokenize(" again"),
+    ].concat();
+    assert_eq!(ids, expected);
+
+    let encoding = tokenizer.encode(&text);
+    assert_eq!(encoding.ids, ids);
+    assert_eq!(encoding.special_tokens_mask.iter().filter(|&&m| m == 1).count(), 2);
+    let eot = encoding.ids.iter().position(|&id| id == config.eot.index).unwrap();
+    assert_eq!(&text[encoding.offsets[eot].0..encoding.offsets[eot].1], config.eot.value);
+    assert_eq!(encoding.word_ids[eot], None);
+    assert_eq!(encoding.word_ids.last(), Some(&Some(2)));
+
+    // Upper-cased after normalization, a control token is never produced from text
+    let shouted = config.eot.value.to_uppercase();
+    assert!(!tokenizer.tokenize(&shouted).contains(&config.eot.index));
+
+    tokenizer.set_special_token_mode(SpecialTokenMode::Plain);
+    let ids = tokenizer.tokenize(&text);
+    assert!(ids.iter().all(|&id| !tokenizer.config.is_control_token(id)));
+    tokenizer.set_mode(EncodingMode::Greedy);
+    let encoding = tokenizer.encode(&text);
+    assert!(encoding.special_tokens_mask.iter().all(|&m| m == 0));
+    assert_offsets_match(&encoding, &text.to_lowercase());
+
+    // The unknown token is only produced for text it stands for, or when matched explicitly
+    let unknown = format!("a {} b", config.unknown.value);
+    for mode in [EncodingMode::Greedy, EncodingMode::Merge] {
+        tokenizer.set_mode(mode);
+        tokenizer.set_special_token_mode(SpecialTokenMode::Plain);
+        assert!(!tokenizer.tokenize(&unknown).contains(&config.unknown.index));
+        tokenizer.set_special_token_mode(SpecialTokenMode::Match);
+        assert!(tokenizer.tokenize(&unknown).contains(&config.unknown.index));
+    }
+    assert_eq!(Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap().special_token_mode, SpecialTokenMode::Plain);
+}
+
+#[test]
+fn added_tokens_get_stable_ids_and_match_whole() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.set_special_token_mode(SpecialTokenMode::Match);
+    let size = tokenizer.vocab_size();
+    let hello = tokenizer.tokenize("hello");
+
+    let tool = AddedToken { rstrip: true, ..AddedToken::new("<tool>", true) };
+    let ferris = AddedToken { single_word: true, normalized: true, ..AddedToken::new("Ferris", false) };
+    let ids = tokenizer.add_tokens(vec![tool, ferris]).unwrap();
+    assert_eq!(ids, vec![size, size + 1]);
+    assert_eq!(tokenizer.add_tokens(vec![AddedToken::new("<tool>", true)]).unwrap(), vec![size]);
+    assert_eq!(tokenizer.tokenize("hello"), hello);
+
+    // Normalized tokens match after lowercasing, single-word tokens only on word boundaries
+    let encoding = tokenizer.encode("hello <tool>   ferris FERRIS ferrisx");
+    assert_eq!(encoding.ids.iter().filter(|&&id| id == size).count(), 1);
+    assert_eq!(encoding.ids.iter().filter(|&&id| id == size + 1).count(), 2);
+    let tool = encoding.ids.iter().position(|&id| id == size).unwrap();
+    assert_eq!(encoding.offsets[tool], (6, 15));
+    assert_eq!(encoding.special_tokens_mask[tool], 1);
+    let ferris = encoding.ids.iter().position(|&id| id == size + 1).unwrap();
+    assert_eq!(encoding.special_tokens_mask[ferris], 0);
+    assert!(encoding.word_ids[ferris].is_some());
+
+    let path = std::env::temp_dir().join("rust_bpe_added_tokens_test.json");
+    tokenizer.save(path.to_str().unwrap()).unwrap();
+    let mut loaded = Tokenizer::load(path.to_str().unwrap()).unwrap();
+    assert_eq!(loaded.config.added_tokens, tokenizer.config.added_tokens);
+    assert_eq!(loaded.tokenize("<tool> ferris"), tokenizer.tokenize("<tool> ferris"));
+
+    // Special added tokens are plain text for untrusted input, other added tokens still match
+    loaded.set_special_token_mode(SpecialTokenMode::Plain);
+    let ids = loaded.tokenize("<tool> ferris");
+    assert!(!ids.contains(&size));
+    assert!(ids.contains(&(size + 1)));
+}
+
+#[test]
+fn decode_stream_matches_detokenize() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.enable_byte_fallback().unwrap();
+    let config = tokenizer.config.clone();
+    let ids = [
+        tokenizer.tokenize("streaming 🦀 ünïcödé"),
+        vec![config.eos.index],
+        tokenizer.tokenize("next"),
+        vec![config.eos.index, config.eot.index],
+        tokenizer.tokenize("日本"),
+        vec![config.eos.index],
+    ].concat();
+
+    let mut stream = DecodeStream::new();
+    let mut output = String::new();
+    for &id in ids.iter() {
+        if let Some(text) = stream.step(&tokenizer, id) {
+            // Only whole characters are ever yielded
+            assert!(!text.contains('\u{FFFD}'));
+            output.push_str(&text);
+        }
+    }
+    output.push_str(&stream.finish(&tokenizer));
+    assert_eq!(output, tokenizer.detokenize(&ids));
+
+    // The first byte of a multi-byte character yields nothing until the rest arrives
+    let crab = tokenizer.tokenize("🦀");
+    assert_eq!(crab.len(), 4);
+    let mut stream = DecodeStream::new();
+    assert_eq!(stream.step(&tokenizer, crab[0]), None);
+    assert_eq!(stream.step(&tokenizer, crab[1]), None);
+    assert_eq!(stream.step(&tokenizer, crab[2]), None);
+    assert_eq!(stream.step(&tokenizer, crab[3]).as_deref(), Some("🦀"));
+}
+
+#[test]
+fn streaming_encoders_ignore_chunk_edges() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.enable_byte_fallback().unwrap();
+    let text = "First line of text\nsecond line, with ünïcödé 🦀\n\n  indented third\nΟΔΟΣ\nlast".repeat(3);
+    let expected = tokenizer.tokenize(&text);
+
+    for size in [1, 2, 3, 7, 64] {
+        let chars: Vec<char> = text.chars().collect();
+        let chunks: Vec<String> = chars.chunks(size).map(|chunk| chunk.iter().collect()).collect();
+        assert_eq!(tokenizer.encode_iter(chunks).collect::<Vec<_>>(), expected);
+    }
+
+    // Reads may split characters, which are reassembled before encoding
+    struct Trickle<'a>(&'a [u8]);
+    impl std::io::Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(5);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+    let ids: std::io::Result<Vec<usize>> = tokenizer.encode_reader(Trickle(text.as_bytes())).collect();
+    assert_eq!(ids.unwrap(), expected);
+
+    let invalid: std::io::Result<Vec<usize>> = tokenizer.encode_reader(&[b'a', 0xFF, b'b'][..]).collect();
+    assert!(invalid.is_err());
+
+    tokenizer.set_pre_tokenizer(PreTokenizerPipeline::gpt2());
+    tokenizer.set_mode(EncodingMode::Merge);
+    let expected = tokenizer.tokenize(&text);
+    let chunks: Vec<String> = text.split_inclusive('\n').map(String::from).collect();
+    assert_eq!(tokenizer.encode_iter(chunks).collect::<Vec<_>>(), expected);
+
+    // Prepend only applies to the start of the whole text, and patterns that could match across a
+    // line break keep the text whole
+    tokenizer.set_pre_tokenizer(PreTokenizerPipeline::default());
+    for normalizer in [
+        vec![NormalizerStep::Prepend { content: "▁".into() }, NormalizerStep::Lowercase],
+        vec![NormalizerStep::Replace { pattern: Pattern::new(r"\s+").unwrap(), content: " ".into() }],
+    ] {
+        tokenizer.set_normalizer(NormalizerPipeline::new(normalizer));
+        assert_eq!(tokenizer.encode_iter(["a\nb"]).collect::<Vec<_>>(), tokenizer.tokenize("a\nb"));
+        let expected = tokenizer.tokenize(&text);
+        for size in [1, 5, 64] {
+            let chars: Vec<char> = text.chars().collect();
+            let chunks: Vec<String> = chars.chunks(size).map(|chunk| chunk.iter().collect()).collect();
+            assert_eq!(tokenizer.encode_iter(chunks).collect::<Vec<_>>(), expected);
+        }
+    }
+
+    // A line too long to buffer is cut where a pre-tokenized piece starts
+    tokenizer.set_normalizer(NormalizerPipeline::uncased());
+    tokenizer.set_pre_tokenizer(PreTokenizerPipeline::gpt2());
+    tokenizer.set_mode(EncodingMode::Greedy);
+    let line = "one long line without breaks ".repeat(10_000);
+    let chunks: Vec<String> = line.as_bytes().chunks(1 << 16).map(|chunk| String::from_utf8(chunk.to_vec()).unwrap()).collect();
+    assert_eq!(tokenizer.encode_iter(chunks).collect::<Vec<_>>(), tokenizer.tokenize(&line));
+}
+
+#[test]
+fn decoder_pipeline_is_configurable_and_saved() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let config = tokenizer.config.clone();
+    let text = "hello , world . it 's done !";
+    let ids = [vec![config.sot.index], tokenizer.tokenize(text), vec![config.eot.index, tokenizer.vocab_size() + 10]].concat();
+
+    // The default decoder keeps special tokens and shows unknown ids as the unknown token
+    let decoded = tokenizer.detokenize(&ids);
+    assert_eq!(decoded, format!("{}{}{}{}", config.sot.value, text, config.eot.value, config.unknown.value));
+
+    let mut decoder = DecoderPipeline::new(vec![DecoderStep::EosAsSpace, DecoderStep::ByteFallback, DecoderStep::CleanUp]);
+    decoder.skip_special_tokens = true;
+    tokenizer.set_decoder(decoder.clone());
+    assert_eq!(tokenizer.detokenize(&ids[..ids.len() - 1]), "hello, world. it's done!");
+
+    let path = std::env::temp_dir().join("rust_bpe_decoder_test.json");
+    tokenizer.save(path.to_str().unwrap()).unwrap();
+    assert_eq!(Tokenizer::load(path.to_str().unwrap()).unwrap().decoder, decoder);
+
+    // Streaming holds back trailing spaces until a clean-up can no longer remove them
+    let mut stream = DecodeStream::new();
+    let mut output = String::new();
+    for &id in ids[..ids.len() - 1].iter() {
+        output.push_str(&stream.step(&tokenizer, id).unwrap_or_default());
+    }
+    output.push_str(&stream.finish(&tokenizer));
+    assert_eq!(output, "hello, world. it's done!");
+
+    let boundary = DecoderStep::WordBoundary { marker: "▁".to_string() };
+    let tokens = vec!["▁hello".to_string(), "▁world".to_string(), "!".to_string()];
+    assert_eq!(boundary.decode_chain(tokens, &config).concat(), "hello world!");
+}
+
+#[test]
+fn failures_return_typed_errors() {
+    let tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let size = tokenizer.vocab_size();
+
+    assert!(matches!(tokenizer.get_tokens(&[0, size]), Err(TokenizerError::UnknownIndex(index)) if index == size));
+    assert!(matches!(tokenizer.get_indices(&["<nope>".to_string()]), Err(TokenizerError::UnknownToken(token)) if token == "<nope>"));
+    assert!(matches!(Tokenizer::load("./src/models/missing.json"), Err(TokenizerError::Io(_))));
+    assert!(matches!(Tokenizer::from_json("{"), Err(TokenizerError::Json(_))));
+    assert!(matches!("bogus".parse::<EncodingMode>(), Err(TokenizerError::InvalidConfig(_))));
+    assert!(matches!(PreTokenizerStep::regex("(unclosed"), Err(TokenizerError::Regex(_))));
+
+    // A vocabulary without the special tokens cannot be indexed
+    let vocabulary = vec!["a".to_string(), "b".to_string()];
+    let missing = Tokenizer::new(vocabulary, Vec::new(), tokenizer.config.clone());
+    assert!(matches!(missing, Err(TokenizerError::MissingSpecialToken(token)) if token == tokenizer.config.sot.value));
+}
+
+#[test]
+fn dropout_samples_valid_reproducible_segmentations() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.set_mode(EncodingMode::Merge);
+    let text = "the unbelievable tokenization of reproducible segmentations";
+
+    // No dropout is the merge-mode encoding, full dropout leaves single characters
+    assert_eq!(tokenizer.encode_with_dropout(text, 0.0, 7).unwrap().ids, tokenizer.tokenize(text));
+    assert_eq!(tokenizer.encode_with_dropout(text, 1.0, 7).unwrap().len(), text.chars().count());
+
+    let first = tokenizer.encode_with_dropout(text, 0.3, 42).unwrap();
+    assert_eq!(first.ids, tokenizer.encode_with_dropout(text, 0.3, 42).unwrap().ids);
+    let samples = tokenizer.sample_encodings(text, 8, 0.3, 42).unwrap();
+    assert_eq!(samples[0].ids, first.ids);
+    assert!(samples.iter().any(|sample| sample.ids != samples[0].ids));
+    for sample in samples.iter() {
+        assert_eq!(tokenizer.detokenize(&sample.ids), text);
+        assert_eq!(sample.offsets.last().unwrap().1, text.len());
+    }
+
+    let nbest = tokenizer.encode_nbest(text, 4, 0.2, 3).unwrap();
+    assert_eq!(nbest.len(), 4);
+    assert_eq!(nbest[0].ids, tokenizer.tokenize(text));
+    assert!(nbest[1..].windows(2).all(|pair| pair[0].len() <= pair[1].len()));
+    for (i, encoding) in nbest.iter().enumerate() {
+        assert!(nbest[i + 1..].iter().all(|other| other.ids != encoding.ids));
+    }
+
+    assert!(matches!(tokenizer.encode_with_dropout(text, 1.5, 0), Err(TokenizerError::InvalidConfig(_))));
+}
+
+#[test]
+fn unigram_trains_prunes_and_segments_by_likelihood() {
+    let output = std::env::temp_dir().join("rust_bpe_unigram.json");
+    let output = output.to_str().unwrap();
+    let source = "the cat sat on the mat the cats sat on the mats \
+        a hat on a cat a bat on a mat that cat sat there then".repeat(3);
+    let mut unigram = Unigram::train(&source, 40, output, TrainOptions::default()).unwrap();
+
+    // Special tokens come first, and pruning stops at the target unless characters would be lost
+    assert_eq!(unigram.vocabulary[..unigram.config.get_values().len()], unigram.config.get_values());
+    assert!(unigram.vocab_size() <= 40);
+    for c in source.chars().filter(|c| !c.is_whitespace()) {
+        assert!(unigram.get_index(&c.to_string()).is_some());
+    }
+    let ids = unigram.tokenize("The cat sat on the mat");
+    assert!(ids.len() < "the cat sat on the mat".len());
+    assert_eq!(unigram.detokenize(&ids), "the cat sat on the mat");
+
+    let loaded = Unigram::load(output).unwrap();
+    assert_eq!(loaded.vocabulary, unigram.vocabulary);
+    assert_eq!(loaded.tokenize(&source), unigram.tokenize(&source));
+
+    // Control tokens are text unless special tokens are matched, and added tokens are always matched
+    assert_ne!(unigram.tokenize("<|sot|>cat")[0], unigram.config.sot.index);
+    unigram.set_special_token_mode(SpecialTokenMode::Match);
+    let ids = unigram.tokenize("<|sot|>cat ü");
+    assert_eq!(ids[0], unigram.config.sot.index);
+    let mut config = unigram.config.clone();
+    config.added_tokens = vec![AddedToken::new("[CAT]", false), AddedToken::new("[DOG]", true)];
+    let vocabulary = [unigram.vocabulary.clone(), vec!["[CAT]".to_string(), "[DOG]".to_string()]].concat();
+    let scores = [unigram.scores.clone(), vec![0.0, 0.0]].concat();
+    let mut added = Unigram::new(vocabulary, scores, config).unwrap();
+    let (cat, dog) = (added.vocab_size() - 2, added.vocab_size() - 1);
+    assert_eq!(added.tokenize("a [CAT] sat"), [added.tokenize("a "), vec![cat], added.tokenize(" sat")].concat());
+    assert!(!added.tokenize("[DOG]").contains(&dog));
+    added.set_special_token_mode(SpecialTokenMode::Match);
+    assert_eq!(added.tokenize("[DOG]"), [dog]);
+
+    // Unseen characters are unknown, or bytes with byte fallback
+    assert_eq!(ids[ids.len() - 2..], [unigram.config.space.index, unigram.config.unknown.index]);
+    let options = TrainOptions { byte_fallback: true, ..Default::default() };
+    let unigram = Unigram::train(&source, 40 + 256, output, options).unwrap();
+    let ids = unigram.tokenize("ü");
+    assert_eq!(unigram.get_tokens(&ids).unwrap(), ["<0xC3>", "<0xBC>"]);
+    assert_eq!(unigram.detokenize(&ids), "ü");
+}
+
+#[test]
+fn wordpiece_splits_words_longest_match_first() {
+    let output = std::env::temp_dir().join("rust_bpe_wordpiece.json");
+    let output = output.to_str().unwrap();
+    let source = "playing played player plays replay replaying display displayed ".repeat(4);
+    let mut wordpiece = WordPiece::train(&source, 20, output, TrainOptions::default()).unwrap();
+
+    // Every character can start or continue a word
+    assert_eq!(wordpiece.vocabulary[..wordpiece.config.get_values().len()], wordpiece.config.get_values());
+    for c in source.chars().filter(|c| !c.is_whitespace()) {
+        assert!(wordpiece.get_index(&c.to_string()).is_some() && wordpiece.get_index(&format!("##{}", c)).is_some());
+    }
+
+    let ids = wordpiece.tokenize("Replayed  displays");
+    let tokens = wordpiece.get_tokens(&ids).unwrap();
+    assert!(tokens.len() < "replayeddisplays".len());
+    assert_eq!(tokens[0], "replay");
+    assert!(tokens[1].starts_with("##"));
+    assert_eq!(wordpiece.detokenize(&ids), "replayed displays");
+
+    // A word with an unknown character, or one that is too long, becomes a single unknown token
+    let unknown = wordpiece.config.unknown.index;
+    assert_eq!(wordpiece.tokenize("play playz"), [wordpiece.tokenize("play"), vec![unknown]].concat());
+    let mut loaded = WordPiece::load(output).unwrap();
+    assert_eq!(loaded.tokenize(&source), wordpiece.tokenize(&source));
+    loaded.max_input_chars_per_word = 5;
+    assert_eq!(loaded.tokenize("replaying"), [unknown]);
+
+    // Control tokens are matched only when special tokens are
+    let sot = wordpiece.config.sot.index;
+    assert_eq!(wordpiece.tokenize("<|sot|>play")[0], unknown);
+    wordpiece.set_special_token_mode(SpecialTokenMode::Match);
+    assert_eq!(wordpiece.tokenize("<|sot|>play"), [vec![sot], wordpiece.tokenize("play")].concat());
+}
+
+#[test]
+fn counts_and_truncates_without_encoding() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    let texts = [
+        "",
+        "The Quick Brown Fox jumps over the lazy dog.",
+        "ΟΔΥΣΣΕΥΣ and İstanbul",
+        "unknown ✓ chars<|eot|>and special tokens",
+    ];
+    for text in texts {
+        assert_eq!(tokenizer.count_tokens(text), tokenizer.tokenize(text).len());
+    }
+    assert_eq!(tokenizer.count_tokens_batch(&texts), texts.map(|text| tokenizer.tokenize(text).len()));
+
+    // Truncation cuts where the nth token ends, and leaves texts within the budget whole
+    let text = texts[1];
+    let offsets = tokenizer.encode(text).offsets;
+    for n in 1..offsets.len() {
+        assert_eq!(tokenizer.truncate_to_tokens(text, n), &text[..offsets[n - 1].1]);
+    }
+    assert_eq!(tokenizer.truncate_to_tokens(text, 0), "");
+    assert_eq!(tokenizer.truncate_to_tokens(text, offsets.len()), text);
+    assert_eq!(tokenizer.truncate_to_tokens_batch(&texts, 3)[1], &text[..offsets[2].1]);
+
+    // Byte tokens of one char are never split
+    tokenizer.enable_byte_fallback().unwrap();
+    let text = "a ✓ b";
+    assert_eq!(tokenizer.count_tokens(text), tokenizer.tokenize(text).len());
+    assert_eq!(tokenizer.truncate_to_tokens(text, 3), "a ");
+
+    // Truncated text never encodes to more tokens than the budget, including where counting needs
+    // the full encoding - with a pre-tokenizer, or in other encoding modes
+    let fits = |tokenizer: &Tokenizer| {
+        assert_eq!(tokenizer.truncate_to_tokens("a😀", 2), "a");
+        for text in texts.iter().chain(&["a😀 b ✓c"]) {
+            for n in 0..=tokenizer.count_tokens(text) {
+                assert!(tokenizer.count_tokens(tokenizer.truncate_to_tokens(text, n)) <= n);
+            }
+        }
+    };
+    fits(&tokenizer);
+    tokenizer.set_pre_tokenizer(PreTokenizerPipeline::gpt2());
+    fits(&tokenizer);
+
+    tokenizer.set_mode(EncodingMode::Merge);
+    for text in texts {
+        assert_eq!(tokenizer.count_tokens(text), tokenizer.tokenize(text).len());
+    }
+    fits(&tokenizer);
+    tokenizer.set_pre_tokenizer(PreTokenizerPipeline::default());
+    fits(&tokenizer);
+}
+
+#[test]
+fn imports_huggingface_bpe_models() {
+    // GPT-2 style: byte-level tokens, one special token standing in for all of them
+    let json = r#"{
+        "version": "1.0",
+        "added_tokens": [{"id": 0, "content": "<|endoftext|>", "single_word": false, "lstrip": false,
+            "rstrip": false, "normalized": false, "special": true}],
+        "normalizer": null,
+        "pre_tokenizer": {"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true, "use_regex": true},
+        "post_processor": null,
+        "decoder": {"type": "ByteLevel", "add_prefix_space": true, "trim_offsets": true, "use_regex": true},
+        "model": {"type": "BPE", "dropout": null, "unk_token": null, "byte_fallback": false,
+            "vocab": {"<|endoftext|>": 0, "h": 1, "e": 2, "l": 3, "o": 4, "Ġ": 5, "w": 6, "r": 7, "d": 8,
+                "he": 9, "ll": 10, "hell": 11, "hello": 12, "Ġw": 13, "or": 14, "Ġwor": 15, "ld": 16,
+                "Ġworld": 17, "Ã": 18, "©": 19, "Ã©": 20},
+            "merges": ["h e", "l l", "he ll", "hell o", "Ġ w", "o r", "Ġw or", "l d", "Ġwor ld", "Ã ©"]}
+    }"#;
+    let mut tokenizer = Tokenizer::from_huggingface_json(json).unwrap();
+    tokenizer.set_special_token_mode(SpecialTokenMode::Match);
+    assert_eq!((&tokenizer.vocabulary[5][..], &tokenizer.vocabulary[18][..], &tokenizer.vocabulary[20][..]), (" ", "<0xC3>", "é"));
+    // `<|endoftext|>` starts and ends text, and the tokens the model lacks get new ids of their own
+    let config = &tokenizer.config;
+    assert_eq!((config.sot.index, config.eot.index, config.eos.index), (0, 0, 0));
+    assert_eq!((config.pad.index, config.mask.index, config.unknown.index), (21, 22, 23));
+    assert_eq!(tokenizer.vocab_size(), 24);
+    let ids = tokenizer.tokenize("Hello world<|endoftext|>hello é");
+    assert_eq!(ids[ids.len() - 4..], [0, 12, 5, 20]);
+    assert_eq!(tokenizer.tokenize("hello world"), [12, 17]);
+    assert_eq!(tokenizer.detokenize(&[12, 17, 0, 18, 19]), "hello world<|endoftext|>é");
+
+    // Llama style: sentencepiece spaces, byte fallback and merges saved as pairs
+    let json = r#"{
+        "added_tokens": [
+            {"id": 0, "content": "<unk>", "special": true},
+            {"id": 1, "content": "<s>", "special": true},
+            {"id": 2, "content": "</s>", "special": true}],
+        "normalizer": {"type": "Sequence", "normalizers": [
+            {"type": "Prepend", "prepend": "▁"},
+            {"type": "Replace", "pattern": {"String": " "}, "content": "▁"}]},
+        "pre_tokenizer": null,
+        "decoder": {"type": "Sequence", "decoders": [
+            {"type": "Replace", "pattern": {"String": "▁"}, "content": " "},
+            {"type": "ByteFallback"}, {"type": "Fuse"}, {"type": "Strip", "content": " ", "start": 1, "stop": 0}]},
+        "model": {"type": "BPE", "unk_token": "<unk>", "byte_fallback": true,
+            "vocab": {"<unk>": 0, "<s>": 1, "</s>": 2, "<0xC3>": 3, "<0xBC>": 4, "▁": 5, "h": 6, "i": 7, "▁h": 8, "▁hi": 9},
+            "merges": [["▁", "h"], ["▁h", "i"]]}
+    }"#;
+    let mut tokenizer = Tokenizer::from_huggingface_json(json).unwrap();
+    tokenizer.set_special_token_mode(SpecialTokenMode::Match);
+    let config = &tokenizer.config;
+    assert_eq!((config.sot.index, config.eot.index, config.unknown.index, config.pad.index), (1, 2, 0, 10));
+    assert_eq!(tokenizer.tokenize("<s>hi hi"), [1, 9, 9]);
+    let ids = tokenizer.tokenize("hü");
+    assert_eq!(ids, [8, 3, 4]);
+    assert_eq!(tokenizer.detokenize(&ids), "hü");
+    assert_eq!(tokenizer.detokenize(&[9, 9]), "hi hi");
+
+    // Models and components without an equivalent are rejected
+    let wordpiece = json.replace(r#""type": "BPE""#, r#""type": "WordPiece""#);
+    assert!(matches!(Tokenizer::from_huggingface_json(&wordpiece), Err(TokenizerError::InvalidConfig(_))));
+    let whitespace = json.replace(r#""pre_tokenizer": null"#, r#""pre_tokenizer": {"type": "Whitespace"}"#);
+    assert!(matches!(Tokenizer::from_huggingface_json(&whitespace), Err(TokenizerError::InvalidConfig(_))));
+
+    // A subset of GPT-2, renumbered in order. "Ġ æ" and "æ ľ" split characters, and the tokens match
+    // GPT-2's own: c, af, Ã©, Ġæ, Ĺ, ¥, æľ, ¬, ĠðŁ, ¦, Ģ
+    let json = r#"{
+        "added_tokens": [],
+        "pre_tokenizer": {"type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true, "use_regex": true},
+        "decoder": {"type": "ByteLevel"},
+        "model": {"type": "BPE", "unk_token": null,
+            "vocab": {"a": 0, "c": 1, "f": 2, "¥": 3, "¦": 4, "©": 5, "¬": 6, "Ã": 7, "æ": 8, "ð": 9, "Ġ": 10,
+                "Ģ": 11, "Ĺ": 12, "ľ": 13, "Ł": 14, "af": 15, "Ã©": 16, "ca": 17, "ðŁ": 18, "Ġæ": 19,
+                "ĠðŁ": 20, "æľ": 21, "æĹ": 22},
+            "merges": ["a f", "Ã ©", "c a", "ð Ł", "Ġ æ", "Ġ ðŁ", "æ ľ", "æ Ĺ"]}
+    }"#;
+    let tokenizer = Tokenizer::from_huggingface_json(json).unwrap();
+    let text = "café 日本 🦀";
+    let ids = tokenizer.tokenize(text);
+    assert_eq!(ids, [1, 15, 16, 19, 12, 3, 21, 6, 20, 4, 11]);
+    assert_eq!(tokenizer.detokenize(&ids), text);
+
+    // Byte-level models export with GPT-2's byte alphabet and import back the same
+    let exported: serde_json::Value = serde_json::from_str(&tokenizer.to_huggingface_json().unwrap()).unwrap();
+    assert_eq!(exported["model"]["vocab"]["Ġæ"], 19);
+    assert_eq!(exported["model"]["merges"][4], serde_json::json!(["Ġ", "æ"]));
+    assert_eq!(exported["pre_tokenizer"]["type"], "ByteLevel");
+    let imported = Tokenizer::from_huggingface_json(&exported.to_string()).unwrap();
+    assert_eq!(imported.vocabulary, tokenizer.vocabulary);
+    assert_eq!(imported.tokenize(text), ids);
+    assert_eq!(imported.detokenize(&ids), text);
+}
+
+#[test]
+fn exports_huggingface_json_that_imports_back() {
+    let mut tokenizer = Tokenizer::load("./src/models/rust-bpe-uncased-25k.json").unwrap();
+    tokenizer.set_mode(EncodingMode::Merge);
+    tokenizer.set_post_processor(Some(PostProcessor::wrap("<|sot|>", "<|eot|>"))).unwrap();
+    let json: serde_json::Value = serde_json::from_str(&tokenizer.to_huggingface_json().unwrap()).unwrap();
+
+    // Specials are in the vocabulary at their own ids, and merges keep their order. HuggingFace
+    // matches every added token, so in `Plain` mode specials are not added tokens and stay text
+    assert_eq!(json["model"]["type"], "BPE");
+    assert_eq!(json["model"]["vocab"]["<|eot|>"], tokenizer.config.eot.index);
+    assert_eq!(json["model"]["merges"][0][0], tokenizer.merge_rules[0].0.as_str());
+    assert_eq!(json["added_tokens"], serde_json::json!([]));
+    assert_eq!(json["normalizer"]["type"], "Lowercase");
+    assert_eq!(json["post_processor"]["single"][0]["SpecialToken"]["id"], "<|sot|>");
+
+    let path = std::env::temp_dir().join("rust_bpe_huggingface.json");
+    let path = path.to_str().unwrap();
+    tokenizer.save_huggingface(path).unwrap();
+    let imported = Tokenizer::load_huggingface(path).unwrap();
+    let text = "The tokenizer exports to HuggingFace, then imports back!<|eot|>";
+    assert_eq!(imported.vocabulary, tokenizer.vocabulary);
+    assert_eq!(imported.config.fixed_tokens().map(|token| &token.value), tokenizer.config.fixed_tokens().map(|token| &token.value));
+    assert!(!tokenizer.tokenize(text).contains(&tokenizer.config.eot.index));
+    assert_eq!(imported.encode(text).ids, tokenizer.encode(text).ids);
+
+    // In `Match` mode specials are added tokens, matched whole on both sides
+    tokenizer.set_special_token_mode(SpecialTokenMode::Match);
+    let json: serde_json::Value = serde_json::from_str(&tokenizer.to_huggingface_json().unwrap()).unwrap();
+    let added = json["added_tokens"].as_array().unwrap();
+    assert!(added.iter().any(|token| token["content"] == "<unk>" && token["special"] == true));
+    assert!(added.windows(2).all(|pair| pair[0]["id"].as_u64() < pair[1]["id"].as_u64()));
+    let mut imported = Tokenizer::from_huggingface_json(&json.to_string()).unwrap();
+    imported.set_special_token_mode(SpecialTokenMode::Match);
+    assert_eq!(tokenizer.tokenize(text).last(), Some(&tokenizer.config.eot.index));
+    assert_eq!(imported.encode(text).ids, tokenizer.encode(text).ids);
+
+    // Steps without a HuggingFace type are written as equivalent replacements and splits
+    tokenizer.set_normalizer(NormalizerPipeline::new(vec![NormalizerStep::StripControl, NormalizerStep::CollapseWhitespace]));
+    tokenizer.set_pre_tokenizer(PreTokenizerPipeline::new(vec![PreTokenizerStep::Whitespace]));
+    let imported = Tokenizer::from_huggingface_json(&tokenizer.to_huggingface_json().unwrap()).unwrap();
+    let text = "Tabs\t and\u{7}  \n\n lines ";
+    assert_eq!(imported.normalize(text), tokenizer.normalize(text));
+    assert_eq!(imported.pre_tokenize(text), tokenizer.pre_tokenize(text));
+}
+
+#[test]
+fn loads_tiktoken_rank_files() {
+    // "a", "b", "c", " ", "ab", "abc", " ab", 0xC3, 0xA9, "é", 0xE2, 0xE2 0x80, 0x80, 0x9C - rank 14 is unused
+    let ranks = "YQ== 0\nYg== 1\nYw== 2\nIA== 3\nYWI= 4\nYWJj 5\nIGFi 6\nww== 7\nqQ== 8\nw6k= 9\n4g== 10\n4oA= 11\ngA== 12\nnA== 13\n";
+    let special_tokens = HashMap::from([("<|endoftext|>".to_string(), 15)]);
+    let mut tokenizer = Tokenizer::from_tiktoken(ranks, CL100K_PATTERN, &special_tokens).unwrap();
+    tokenizer.set_special_token_mode(SpecialTokenMode::Match);
+
+    // Ids equal ranks, and bytes that are not text on their own are byte tokens. The padding, mask and
+    // unknown tokens come after the ranks
+    assert_eq!(tokenizer.vocab_size(), 19);
+    assert!([tokenizer.config.pad.index, tokenizer.config.mask.index, tokenizer.config.unknown.index].iter().all(|&index| index >= 16));
+    assert_eq!(tokenizer.vocabulary[9], "é");
+    assert_eq!((&tokenizer.vocabulary[7][..], &tokenizer.vocabulary[11][..], &tokenizer.vocabulary[14][..]), ("<0xC3>", "<0xE2><0x80>", ""));
+    assert_eq!(tokenizer.config.eot.index, 15);
+
+    assert_eq!(tokenizer.tokenize("abc ab<|endoftext|>é"), [5, 6, 15, 9]);
+    // "“" is 0xE2 0x80 0x9C - the first two bytes merge like any other pair
+    let ids = tokenizer.tokenize("“ab");
+    assert_eq!(ids, [11, 13, 4]);
+    assert_eq!(tokenizer.detokenize(&ids), "“ab");
+    assert_eq!(tokenizer.count_tokens("abc ab abc"), 4);
+
+    let invalid = Tokenizer::from_tiktoken("YQ== zero\n", CL100K_PATTERN, &HashMap::new());
+    assert!(matches!(invalid, Err(TokenizerError::InvalidConfig(_))));
+}
+
+// Standard base64 of a token's bytes, for writing rank files
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let value = chunk.iter().enumerate().fold(0u32, |value, (i, &byte)| value | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => text.push(ALPHABET[(value >> (18 - 6 * i) & 63) as usize] as char),
+                false => text.push('='),
+            }
+        }
+    }
+    text
+}
+
+#[test]
+fn merges_tiktoken_ranks_across_characters() {
+    // Every byte, then "a" 0xC3, "é" and 0xA9 "b" - the first and last split "é"
+    let mut ranks: String = (0..=u8::MAX).map(|byte| format!("{} {}\n", base64(&[byte]), byte)).collect();
+    ranks.push_str("YcM= 256\nw6k= 257\nqWI= 258\n");
+    let tokenizer = Tokenizer::from_tiktoken(&ranks, CL100K_PATTERN, &HashMap::new()).unwrap();
+    assert_eq!(tokenizer.tokenize("aéb"), [256, 258]);
+    assert_eq!(tokenizer.detokenize(&[256, 258]), "aéb");
+    assert_eq!(tokenizer.tokenize("é"), [257]);
+
+    // The split pattern is kept before a ByteLevel step that only maps bytes
+    let imported = Tokenizer::from_huggingface_json(&tokenizer.to_huggingface_json().unwrap()).unwrap();
+    assert_eq!(imported.vocabulary, tokenizer.vocabulary);
+    for text in ["aéb", "é", "Ünïcödé text\n  with 🦀 spaces"] {
+        assert_eq!(imported.tokenize(text), tokenizer.tokenize(text));
+        assert_eq!(imported.detokenize(&imported.tokenize(text)), text);
+    }
+
+    // Subsets of the r50k_base and cl100k_base rank files give the ids tiktoken does
+    let text = "café 日本 🦀";
+    let r50k = "YQ== 64\nYw== 66\nZg== 69\npQ== 98\npg== 99\nqQ== 102\nrA== 105\nww== 127\n5g== 162\n8A== 172\nIA== 220\ngA== 222\nlw== 245\nnA== 250\nnw== 253\nYWY= 1878\nw6k= 2634\nY2E= 6888\n8J8= 8582\nIOY= 10545\nIPCf 12520\n5pw= 17312\n5pc= 33768\n";
+    let tokenizer = Tokenizer::from_tiktoken(r50k, GPT2_PATTERN, &HashMap::new()).unwrap();
+    let ids = tokenizer.tokenize(text);
+    assert_eq!(ids, [66, 1878, 2634, 10545, 245, 98, 17312, 105, 12520, 99, 222]);
+    assert_eq!(tokenizer.detokenize(&ids), text);
+
+    let cl100k = "YQ== 64\nYw== 66\nZg== 69\npQ== 98\npg== 99\nqQ== 102\nrA== 105\nww== 127\n5g== 162\n8A== 172\nIA== 220\ngA== 222\nlw== 245\nnA== 250\nnw== 253\nY2E= 936\nw6k= 978\nYWY= 2642\n5pw= 4916\n5pc= 6079\nIOY= 6704\n5pel 9080\n8J8= 9468\nIPCf 11410\n5pys 22656\nZsOp 59958\nY2Fm 69896\nIOaXpQ== 76502\n";
+    let tokenizer = Tokenizer::from_tiktoken(cl100k, CL100K_PATTERN, &HashMap::new()).unwrap();
+    let ids = tokenizer.tokenize(text);
+    assert_eq!(ids, [936, 59958, 76502, 22656, 11410, 99, 222]);
+    assert_eq!(tokenizer.detokenize(&ids), text);
+}